@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+
+use crate::enhance::Enhance;
+use crate::mix::{join, Curve, JoinSettings, Joined, Sample};
+use crate::sbf::{SBFIndexEntry, SBF};
+
+pub const DEFAULT_CONFIG_PATH: &str = "reordering_config.json";
+const DEFAULTS_KEY: &str = "$defaults";
+
+// Track keys are either a bare ident (`m3`) or qualified by the SBF it comes from (`EXP1.sbf:m3`).
+pub fn split_track_key(key: &str) -> (Option<&str>, &str) {
+    match key.split_once(':') {
+        Some((file, track)) => (Some(file), track),
+        None => (None, key),
+    }
+}
+
+pub fn qualified_key(file: &str, track: &str) -> String {
+    format!("{file}:{track}")
+}
+
+/// The SBF a track key refers to (the first one containing a bare ident) and the ident.
+pub fn find_track<'a, 'k>(sbfs: &'a [SBF], key: &'k str) -> anyhow::Result<(&'a SBF, &'k str)> {
+    let (file, ident) = split_track_key(key);
+    let sbf = sbfs
+        .iter()
+        .filter(|sbf| file.is_none_or(|file| sbf.name.eq_ignore_ascii_case(file)))
+        .find(|sbf| sbf.grouped_chunks.contains_key(ident))
+        .ok_or(anyhow!("track {key} not found"))?;
+    Ok((sbf, ident))
+}
+
+/// A config entry matched to the SBF it will be rendered from.
+pub struct ResolvedTrack<'a> {
+    pub key: &'a str,
+    pub ident: &'a str,
+    pub sbf: &'a SBF,
+    pub arrangement: &'a TrackArrangement,
+    // other SBFs that also contain the ident of an unqualified key
+    pub also_in: Vec<&'a SBF>,
+}
+
+impl ResolvedTrack<'_> {
+    pub fn file_stem(&self) -> &str {
+        self.sbf.name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&self.sbf.name)
+    }
+}
+
+/// The output name of the track `ident` from the SBF `file`: the ident, with the file stem in front
+/// (`EXP1_m3`) when it `collides` with a track of the same ident from another SBF.
+pub fn output_name(file: &str, ident: &str, collides: bool) -> String {
+    match collides {
+        true => format!("{}_{ident}", file.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file)),
+        false => ident.to_owned(),
+    }
+}
+
+// Tracks rendered from more than one SBF get the file stem in their output name so they don't overwrite each other.
+pub fn output_names(resolved: &[ResolvedTrack]) -> Vec<String> {
+    resolved
+        .iter()
+        .map(|r| {
+            let collides = resolved.iter().any(|o| o.ident == r.ident && !std::ptr::eq(o.sbf, r.sbf));
+            output_name(&r.sbf.name, r.ident, collides)
+        })
+        .collect()
+}
+
+pub enum Resolution<'a> {
+    Found(ResolvedTrack<'a>),
+    UnknownFile { file: &'a str },
+    MissingTrack { file: Option<&'a str> },
+}
+
+// Join settings that are only partially specified at some level of the config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JoinOverride {
+    pub crossfade_ms: Option<u32>,
+    pub curve: Option<Curve>,
+    pub declick_ms: Option<u32>,
+}
+
+impl JoinOverride {
+    pub fn is_empty(&self) -> bool {
+        self == &JoinOverride::default()
+    }
+
+    pub fn apply(&self, settings: &mut JoinSettings) {
+        if let Some(crossfade_ms) = self.crossfade_ms {
+            settings.crossfade_ms = crossfade_ms;
+        }
+        if let Some(curve) = self.curve {
+            settings.curve = curve;
+        }
+        if let Some(declick_ms) = self.declick_ms {
+            settings.declick_ms = declick_ms;
+        }
+    }
+
+    fn from_json(obj: &Map<String, Value>) -> anyhow::Result<Self> {
+        let ms = |key: &str| -> anyhow::Result<Option<u32>> {
+            obj.get(key)
+                .map(|v| {
+                    v.as_u64()
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or(anyhow!("{key} must be a non-negative integer, got {v}"))
+                })
+                .transpose()
+        };
+        let curve = obj
+            .get("curve")
+            .map(|v| Curve::parse(v.as_str().ok_or(anyhow!("curve must be a string, got {v}"))?))
+            .transpose()?;
+        Ok(Self {
+            crossfade_ms: ms("crossfade_ms")?,
+            curve,
+            declick_ms: ms("declick_ms")?,
+        })
+    }
+
+    fn write_json(&self, obj: &mut Map<String, Value>) {
+        if let Some(crossfade_ms) = self.crossfade_ms {
+            obj.insert("crossfade_ms".into(), crossfade_ms.into());
+        }
+        if let Some(curve) = self.curve {
+            obj.insert("curve".into(), curve.name().into());
+        }
+        if let Some(declick_ms) = self.declick_ms {
+            obj.insert("declick_ms".into(), declick_ms.into());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackArrangement {
+    pub order: Vec<String>,
+    pub joins: JoinOverride,
+    // keyed by the suffix of the segment the join leads into
+    pub per_join: BTreeMap<String, JoinOverride>,
+}
+
+impl TrackArrangement {
+    pub fn new(order: Vec<String>) -> Self {
+        Self {
+            order,
+            ..Default::default()
+        }
+    }
+
+    pub fn join_settings(&self, defaults: &JoinOverride) -> Vec<JoinSettings> {
+        self.order
+            .iter()
+            .skip(1)
+            .map(|suffix| {
+                let mut settings = JoinSettings::default();
+                defaults.apply(&mut settings);
+                self.joins.apply(&mut settings);
+                if let Some(o) = self.per_join.get(suffix) {
+                    o.apply(&mut settings);
+                }
+                settings
+            })
+            .collect()
+    }
+
+    fn from_json(v: &Value) -> anyhow::Result<Self> {
+        match v {
+            Value::Array(_) => Ok(Self::new(suffix_list(v)?)),
+            Value::Object(obj) => {
+                let order = suffix_list(obj.get("order").ok_or(anyhow!("missing \"order\""))?)?;
+                let mut per_join = BTreeMap::new();
+                if let Some(joins) = obj.get("joins") {
+                    let joins = joins.as_object().ok_or(anyhow!("\"joins\" must be an object"))?;
+                    for (suffix, o) in joins {
+                        let o = o.as_object().ok_or(anyhow!("join {suffix:?} must be an object"))?;
+                        per_join.insert(suffix.to_owned(), JoinOverride::from_json(o).context(format!("join {suffix:?}"))?);
+                    }
+                }
+                Ok(Self {
+                    order,
+                    joins: JoinOverride::from_json(obj)?,
+                    per_join,
+                })
+            }
+            _ => Err(anyhow!("expected a list of suffixes or an object, got {v}")),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let order = Value::from(self.order.clone());
+        if self.joins.is_empty() && self.per_join.is_empty() {
+            return order;
+        }
+        let mut obj = Map::new();
+        obj.insert("order".into(), order);
+        self.joins.write_json(&mut obj);
+        if !self.per_join.is_empty() {
+            let mut joins = Map::new();
+            for (suffix, o) in &self.per_join {
+                let mut j = Map::new();
+                o.write_json(&mut j);
+                joins.insert(suffix.to_owned(), j.into());
+            }
+            obj.insert("joins".into(), joins.into());
+        }
+        obj.into()
+    }
+}
+
+fn suffix_list(v: &Value) -> anyhow::Result<Vec<String>> {
+    v.as_array()
+        .ok_or(anyhow!("expected a list of suffixes, got {v}"))?
+        .iter()
+        .map(|s| s.as_str().map(|s| s.to_owned()).ok_or(anyhow!("suffix must be a string, got {s}")))
+        .collect()
+}
+
+/// Contents of `reordering_config.json`: the segment order of every track plus crossfade/de-click
+/// settings. A track is either a plain list of suffixes or an object with an `order` list and
+/// join settings; `$defaults` holds the settings used where a track doesn't override them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArrangementConfig {
+    pub defaults: JoinOverride,
+    pub tracks: BTreeMap<String, TrackArrangement>,
+}
+
+impl ArrangementConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).context(format!("couldn't read {path:?}"))?;
+        Self::from_json(&content).context(format!("invalid arrangement config {path:?}"))
+    }
+
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_str(content)?;
+        let obj = value.as_object().ok_or(anyhow!("top level must be an object"))?;
+        let mut config = Self::default();
+        for (key, v) in obj {
+            if key == DEFAULTS_KEY {
+                let defaults = v.as_object().ok_or(anyhow!("{DEFAULTS_KEY} must be an object"))?;
+                config.defaults = JoinOverride::from_json(defaults).context(DEFAULTS_KEY)?;
+            } else {
+                config.tracks.insert(key.to_owned(), TrackArrangement::from_json(v).context(format!("track {key:?}"))?);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut obj = Map::new();
+        if !self.defaults.is_empty() {
+            let mut defaults = Map::new();
+            self.defaults.write_json(&mut defaults);
+            obj.insert(DEFAULTS_KEY.into(), defaults.into());
+        }
+        for (name, track) in &self.tracks {
+            obj.insert(name.to_owned(), track.to_json());
+        }
+        serde_json::to_string_pretty(&Value::Object(obj)).unwrap()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_json()).context(format!("couldn't write {path:?}"))
+    }
+
+    pub fn resolve<'a>(&'a self, key: &'a str, sbfs: &'a [SBF]) -> Resolution<'a> {
+        let arrangement = &self.tracks[key];
+        let (file, ident) = split_track_key(key);
+        let candidates = match file {
+            Some(file) => {
+                let Some(sbf) = sbfs.iter().find(|sbf| sbf.name.eq_ignore_ascii_case(file)) else {
+                    return Resolution::UnknownFile { file };
+                };
+                vec![sbf]
+            }
+            None => sbfs.iter().collect(),
+        };
+        let mut found_in = candidates.into_iter().filter(|sbf| sbf.grouped_chunks.contains_key(ident));
+        match found_in.next() {
+            Some(sbf) => Resolution::Found(ResolvedTrack {
+                key,
+                ident,
+                sbf,
+                arrangement,
+                also_in: found_in.collect(),
+            }),
+            None => Resolution::MissingTrack { file },
+        }
+    }
+
+    pub fn resolve_all<'a>(&'a self, sbfs: &'a [SBF]) -> Vec<ResolvedTrack<'a>> {
+        self.tracks
+            .keys()
+            .filter_map(|key| match self.resolve(key, sbfs) {
+                Resolution::Found(resolved) => Some(resolved),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The entry `play` edits for `ident` in `sbf`: the qualified key, or else the bare one, which
+    // `play` replaces by the qualified key when saving so the track isn't configured twice.
+    pub fn find_for(&self, sbf: &SBF, ident: &str) -> Option<(&String, &TrackArrangement)> {
+        sbf.grouped_chunks.get(ident)?;
+        self.tracks
+            .iter()
+            .find(|(key, _)| matches!(split_track_key(key), (Some(file), track) if track == ident && sbf.name.eq_ignore_ascii_case(file)))
+            .or(self.tracks.get_key_value(ident))
+    }
+
+    pub fn render(&self, sbf: &SBF, ident: &str, track: &TrackArrangement) -> anyhow::Result<Joined> {
+        self.render_with(sbf, ident, track, |e| sbf.decode_segment(e))
+    }
+
+    pub fn render_enhanced(&self, sbf: &SBF, ident: &str, track: &TrackArrangement, settings: &Enhance) -> anyhow::Result<Joined<f32>> {
+        self.render_with(sbf, ident, track, |e| sbf.decode_segment_enhanced(e, settings))
+    }
+
+    fn render_with<S: Sample>(
+        &self,
+        sbf: &SBF,
+        ident: &str,
+        track: &TrackArrangement,
+        decode: impl Fn(&SBFIndexEntry) -> Vec<S>,
+    ) -> anyhow::Result<Joined<S>> {
+        let segments = track
+            .order
+            .iter()
+            .map(|suffix| {
+                sbf.segment(ident, suffix)
+                    .map(&decode)
+                    .ok_or(anyhow!("segment {ident}{suffix} not found"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(join(segments, &track.join_settings(&self.defaults)))
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use clap::{Args, ValueEnum};
+use dfbhd_mus::arrangement::{find_track, ArrangementConfig, TrackArrangement, DEFAULT_CONFIG_PATH};
+use dfbhd_mus::encoder::SampleFormat;
+use dfbhd_mus::enhance::DecodeMode;
+use dfbhd_mus::mix::{duration_to_frames, layout, parse_time};
+use dfbhd_mus::sbf::{CHANNELS, SAMPLE_RATE};
+use dfbhd_mus::wav::{self, WavFormat, WavWriter};
+use crate::game::GameArgs;
+
+// samples per write
+const PIECE: usize = 1 << 14;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Order {
+    Config,
+    Index,
+}
+
+#[derive(Args)]
+pub struct CatArgs {
+    /// Track name, bare (`m3`) or qualified (`EXP1.sbf:m3`)
+    track: String,
+    #[command(flatten)]
+    game: GameArgs,
+    /// Arrangements to use with `--order config`
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Segment order: as configured (falls back to index order) or as stored
+    #[arg(long, value_enum, default_value = "config")]
+    order: Order,
+    /// Inclusive range of segment suffixes: `first..last`, `first..`, `..last` or one suffix
+    #[arg(long)]
+    segments: Option<String>,
+    /// Start time (`90`, `1:30.5`, `1:01:30`)
+    #[arg(long, value_parser = parse_time)]
+    start: Option<Duration>,
+    /// End time
+    #[arg(long, value_parser = parse_time)]
+    end: Option<Duration>,
+    /// Headerless little-endian PCM instead of WAV
+    #[arg(long)]
+    raw: bool,
+    /// Sample format: 16, 24 or float
+    #[arg(long, default_value = "16", value_parser = SampleFormat::parse)]
+    bits: SampleFormat,
+    /// classic or enhanced
+    #[arg(long, default_value = "classic", value_parser = DecodeMode::parse)]
+    decoder: DecodeMode,
+}
+
+// `first..last`, `first..`, `..last` or a single suffix, inclusive
+fn segment_range(spec: &str, order: &[String]) -> anyhow::Result<(usize, usize)> {
+    if order.is_empty() {
+        return Err(anyhow!("no segments to pick {spec:?} from"));
+    }
+    let (first, last) = spec.split_once("..").unwrap_or((spec, spec));
+    let position = |suffix: &str| order.iter().position(|s| s == suffix).ok_or(anyhow!("no segment {suffix:?} in {order:?}"));
+    let first = if first.is_empty() { 0 } else { position(first)? };
+    let last = if last.is_empty() { order.len() - 1 } else { position(last)? };
+    if first > last {
+        return Err(anyhow!("segment range {spec:?} is backwards"));
+    }
+    Ok((first, last))
+}
+
+// the samples in `range`, as far as there are any
+fn cut<S>(mut samples: Vec<S>, range: Range<usize>) -> Vec<S> {
+    samples.truncate(range.end);
+    samples.drain(..range.start.min(samples.len()));
+    samples
+}
+
+pub fn cat(args: CatArgs) -> anyhow::Result<ExitCode> {
+    let sbfs = args.game.load()?;
+    let config = ArrangementConfig::load(&args.config)?;
+    let (sbf, ident) = find_track(&sbfs, &args.track)?;
+    let index_order = TrackArrangement::new(sbf.grouped_chunks[ident].iter().map(|e| e.suffix.to_owned()).collect());
+
+    // index order is the raw concatenation, without the join settings of the config
+    let unconfigured = ArrangementConfig::default();
+    let configured = config.find_for(sbf, ident);
+    let (render_config, mut arrangement) = match configured {
+        Some((_, arrangement)) if args.order == Order::Config => (&config, arrangement.clone()),
+        _ => {
+            if args.order == Order::Config {
+                eprintln!("note: {} isn't in {:?}, using index order", args.track, args.config);
+            }
+            (&unconfigured, index_order)
+        }
+    };
+    if let Some(spec) = &args.segments {
+        let (first, last) = segment_range(spec, &arrangement.order)?;
+        arrangement.order = arrangement.order[first..=last].to_vec();
+    }
+
+    // where the segments end up, from the block headers, so only those in the time range are decoded
+    let lengths = arrangement.order.iter()
+        .map(|suffix| sbf.segment(ident, suffix).map(|e| sbf.segment_frames(e) as usize).ok_or(anyhow!("segment {ident}{suffix} not found")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (offsets, frames) = layout(&lengths, &arrangement.join_settings(&render_config.defaults));
+    let start = args.start.map_or(0, duration_to_frames).min(frames);
+    let end = args.end.map_or(frames, duration_to_frames).clamp(start, frames);
+    let overlapping = (0..lengths.len()).filter(|&idx| offsets[idx] < end && offsets[idx] + lengths[idx] > start).collect::<Vec<_>>();
+    let (classic, enhanced) = match (overlapping.first(), overlapping.last()) {
+        (Some(&first), Some(&last)) => {
+            // with a neighbour on either side, so the joins at the edges come out as in the whole track
+            let (first, last) = (first.saturating_sub(1), (last + 1).min(lengths.len() - 1));
+            let part = TrackArrangement { order: arrangement.order[first..=last].to_vec(), ..arrangement.clone() };
+            let ch = CHANNELS as usize;
+            let range = (start - offsets[first]) * ch..(end - offsets[first]) * ch;
+            match &args.decoder {
+                DecodeMode::Classic => (cut(render_config.render(sbf, ident, &part)?.samples, range), vec![]),
+                DecodeMode::Enhanced(settings) => {
+                    (vec![], cut(render_config.render_enhanced(sbf, ident, &part, settings)?.samples, range))
+                }
+            }
+        }
+        _ => (vec![], vec![]),
+    };
+    let frames = (classic.len() + enhanced.len()) / CHANNELS as usize;
+
+    let stdout = std::io::stdout().lock();
+    if args.raw {
+        let mut out = BufWriter::new(stdout);
+        for piece in classic.chunks(PIECE) {
+            wav::write_i16(&mut out, args.bits, piece)?;
+        }
+        for piece in enhanced.chunks(PIECE) {
+            wav::write_f32(&mut out, args.bits, piece)?;
+        }
+        out.flush()?;
+    } else {
+        let format = WavFormat {
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            sample_format: args.bits,
+        };
+        // the length is known before the first sample, so the header never needs patching
+        let mut out = WavWriter::new(stdout, format, frames as u64, vec![])?;
+        for piece in classic.chunks(PIECE) {
+            out.write_i16(piece)?;
+        }
+        for piece in enhanced.chunks(PIECE) {
+            out.write_f32(piece)?;
+        }
+        out.finish()?.flush()?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
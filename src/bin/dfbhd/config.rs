@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::{anyhow, Context};
+use clap::Subcommand;
+use dfbhd_mus::arrangement::{split_track_key, ArrangementConfig, Resolution, DEFAULT_CONFIG_PATH};
+use dfbhd_mus::interchange::{export, import, timings, Format};
+use dfbhd_mus::merge::{diff, merge};
+use crate::game::FileArgs;
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Show added/removed tracks and moved segments, exits non-zero on differences
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+    /// Three-way merge per track, exits non-zero on conflicts (which keep `ours`)
+    Merge {
+        base: PathBuf,
+        ours: PathBuf,
+        theirs: PathBuf,
+        /// Write the result here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the arrangement of a track
+    Export {
+        track: String,
+        /// rust, json, csv or audacity (defaults to the extension of --output, then json)
+        #[arg(long, value_parser = Format::parse)]
+        format: Option<Format>,
+        /// Needed for the segment timings of csv and audacity exports
+        #[arg(short, long)]
+        game_dir: Option<PathBuf>,
+        #[command(flatten)]
+        files: FileArgs,
+        /// Write here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+    },
+    /// Replace the segment order of a track with an exported arrangement
+    Import {
+        track: String,
+        file: PathBuf,
+        /// rust, json, csv or audacity (defaults to the extension of the file)
+        #[arg(long, value_parser = Format::parse)]
+        format: Option<Format>,
+        #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+    },
+}
+
+pub fn config(command: ConfigCommand) -> anyhow::Result<ExitCode> {
+    match command {
+        ConfigCommand::Diff { old, new } => {
+            let changes = diff(&ArrangementConfig::load(&old)?, &ArrangementConfig::load(&new)?);
+            for change in &changes {
+                println!("{change}");
+            }
+            if !changes.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        ConfigCommand::Merge { base, ours, theirs, output } => {
+            let merged = merge(
+                &ArrangementConfig::load(&base)?,
+                &ArrangementConfig::load(&ours)?,
+                &ArrangementConfig::load(&theirs)?,
+            );
+            for conflict in &merged.conflicts {
+                eprintln!("conflict: {conflict}");
+            }
+            match output {
+                Some(output) => merged.config.save(&output)?,
+                None => println!("{}", merged.config.to_json()),
+            }
+            if !merged.conflicts.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        ConfigCommand::Export { track: key, format, game_dir, files, output, config: config_path } => {
+            let config = ArrangementConfig::load(&config_path)?;
+            let format = format
+                .or_else(|| output.as_deref().and_then(|o| Format::from_path(o).ok()))
+                .unwrap_or(Format::Json);
+            let track = config.tracks.get(&key).ok_or(anyhow!("{key} is not in {config_path:?}"))?;
+            let segment_timings = if format.needs_timings() {
+                let game_dir = game_dir.ok_or(anyhow!("{format:?} export needs --game-dir to compute segment timings"))?;
+                let sbfs = files.discover(&game_dir)?.load()?;
+                let Resolution::Found(resolved) = config.resolve(&key, &sbfs) else {
+                    return Err(anyhow!("{key} not found in the SBFs"));
+                };
+                let joined = config.render(resolved.sbf, resolved.ident, track)?;
+                Some(timings(&joined))
+            } else {
+                None
+            };
+            let exported = export(format, split_track_key(&key).1, &track.order, segment_timings.as_deref())?;
+            match output {
+                Some(output) => std::fs::write(&output, exported).context(format!("couldn't write {output:?}"))?,
+                None => print!("{exported}"),
+            }
+        }
+        ConfigCommand::Import { track: key, file, format, config: config_path } => {
+            let format = match format {
+                Some(format) => format,
+                None => Format::from_path(&file)?,
+            };
+            let order = import(format, split_track_key(&key).1, &std::fs::read_to_string(&file).context(format!("couldn't read {file:?}"))?)?;
+            let mut config = ArrangementConfig::load(&config_path)?;
+            // keep the join settings of an existing entry, only the order is imported
+            config.tracks.entry(key)
+                .or_default()
+                .order = order;
+            config.save(&config_path)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
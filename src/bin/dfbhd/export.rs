@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use clap::Args;
+use dfbhd_mus::arrangement::{output_name, output_names, split_track_key, ArrangementConfig, ResolvedTrack, TrackArrangement, DEFAULT_CONFIG_PATH};
+use dfbhd_mus::encoder::{ExportOptions, OutputFormat, SampleFormat, TrackInfo};
+use dfbhd_mus::enhance::{DecodeMode, Enhance};
+use dfbhd_mus::fingerprint::{Fingerprint, FingerprintDatabase, Identification, DEFAULT_FINGERPRINTS_PATH};
+use dfbhd_mus::markers::Sidecars;
+use dfbhd_mus::metadata::{MetadataFile, TrackMetadata, DEFAULT_METADATA_PATH};
+use dfbhd_mus::output::{Action, BuildKey, Existing, OutputDir, OutputFile, Plan};
+use dfbhd_mus::resample::{Quality, Resample};
+use dfbhd_mus::segments::{export_segments, SegmentFiles};
+use dfbhd_mus::titles::{TitleDatabase, DEFAULT_TITLES_PATH};
+use dfbhd_mus::sbf::{export_prefix, SBFIndexEntry, CHANNELS, SAMPLE_RATE, SBF};
+use dfbhd_mus::validate::{validate, Problem};
+use crate::game::{GameArgs, TrackArgs};
+use crate::progress::{Batch, Track};
+use rayon::prelude::*;
+
+/// Flags shared by `extract` and `render`.
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    game: GameArgs,
+    #[command(flatten)]
+    tracks: TrackArgs,
+    /// Files are written to <OUTPUT_DIR>/wav or <OUTPUT_DIR>/flac
+    #[arg(short, long)]
+    output_dir: PathBuf,
+    /// wav or flac
+    #[arg(long, default_value = "wav", value_parser = OutputFormat::parse)]
+    format: OutputFormat,
+    /// Tags per track
+    #[arg(long, default_value = DEFAULT_METADATA_PATH)]
+    metadata: PathBuf,
+    /// Title overrides
+    #[arg(long, default_value = DEFAULT_TITLES_PATH)]
+    titles: PathBuf,
+    /// Known releases (see `identify --add`), for album, year and release tags
+    #[arg(long, default_value = DEFAULT_FINGERPRINTS_PATH)]
+    fingerprints: PathBuf,
+    /// Name files by track ident only, without the title
+    #[arg(long)]
+    ident_names: bool,
+    /// Write a cue sheet next to every track
+    #[arg(long)]
+    cue_sheet: bool,
+    /// Write segment timings next to every track
+    #[arg(long)]
+    timings: bool,
+    /// Also (or only) write every segment as its own file: off (default), also or only; `extract` without
+    /// --group-by-prefix only writes segments
+    #[arg(long, value_parser = SegmentFiles::parse)]
+    segments: Option<SegmentFiles>,
+    /// Resample to 22050, 44100 or 48000 Hz
+    #[arg(long, value_parser = Resample::parse_rate)]
+    sample_rate: Option<u32>,
+    /// fast, standard or high
+    #[arg(long, default_value = "standard", value_parser = Quality::parse)]
+    resample_quality: Quality,
+    /// classic (bit-exact 16-bit) or enhanced (float, smoothed gain)
+    #[arg(long, default_value = "classic", value_parser = DecodeMode::parse)]
+    decoder: DecodeMode,
+    /// Gain ramp length of the enhanced decoder, 0 for hard steps
+    #[arg(long, default_value_t = Enhance::default().gain_ramp_ms)]
+    gain_ramp_ms: f32,
+    /// Downward expander threshold of the enhanced decoder, in quantization steps
+    #[arg(long)]
+    noise_reduction: Option<f32>,
+    /// Output sample format: 16, 24 or float
+    #[arg(long, default_value = "16", value_parser = SampleFormat::parse)]
+    bits: SampleFormat,
+    /// List what would be written, replaced and removed, without exporting anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Replace files in the output directory that dfbhd didn't write
+    #[arg(long, conflicts_with = "skip_existing")]
+    overwrite: bool,
+    /// Keep files that exist in the output directory, only export the missing ones
+    #[arg(long)]
+    skip_existing: bool,
+    /// Export every track, also those unchanged since the last run
+    #[arg(long)]
+    force: bool,
+    /// Tracks exported at the same time (default: one per CPU core)
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+}
+
+impl ExportArgs {
+    fn options(&self) -> ExportOptions {
+        let mut decoder = self.decoder;
+        if let DecodeMode::Enhanced(settings) = &mut decoder {
+            *settings = Enhance {
+                gain_ramp_ms: self.gain_ramp_ms,
+                noise_reduction: self.noise_reduction,
+            };
+        }
+        ExportOptions {
+            format: self.format,
+            titled_names: !self.ident_names,
+            sidecars: Sidecars {
+                cue_sheet: self.cue_sheet,
+                timings: self.timings,
+            },
+            segment_files: self.segments.unwrap_or_default(),
+            resample: self.sample_rate.map(|rate| Resample { rate, quality: self.resample_quality }),
+            decoder,
+            sample_format: self.bits,
+        }
+    }
+
+    fn thread_pool(&self) -> anyhow::Result<()> {
+        // 0 is rayon's default, a thread per core
+        rayon::ThreadPoolBuilder::new().num_threads(self.jobs.map_or(0, NonZeroUsize::get)).build_global()?;
+        Ok(())
+    }
+
+    // tags, with the releases `sbfs` are identified as; and a warning for every modified SBF
+    fn metadata(&self, sbfs: &[SBF]) -> anyhow::Result<(MetadataFile, HashMap<String, String>)> {
+        let mut metadata = MetadataFile::load(&self.metadata)?;
+        metadata.titles = TitleDatabase::load(&self.titles)?;
+        let fingerprints = FingerprintDatabase::load(&self.fingerprints)?;
+        let mut modified = HashMap::new();
+        for sbf in sbfs {
+            let identification = fingerprints.identify(&sbf.name, &Fingerprint::of(sbf));
+            if let Identification::Modified { release, .. } = &identification {
+                let warning = format!("{} is a modified {} from {}", sbf.name, release.file, release.name());
+                eprintln!("warning: {warning} (see `dfbhd identify`)");
+                modified.insert(sbf.name.clone(), warning);
+            }
+            if let Some(tags) = identification.tags() {
+                metadata.releases.insert(sbf.name.clone(), tags);
+            }
+        }
+        Ok((metadata, modified))
+    }
+
+    fn output(&self) -> anyhow::Result<OutputDir> {
+        let existing = if self.overwrite {
+            Existing::Overwrite
+        } else if self.skip_existing {
+            Existing::Skip
+        } else {
+            Existing::Refuse
+        };
+        OutputDir::open(&self.output_dir, self.format, existing, self.force)
+    }
+
+    // the file name of a track, titled unless --ident-names
+    fn file_name(&self, metadata: &MetadataFile, sbf: &SBF, ident: &str, name: &str) -> String {
+        if self.ident_names {
+            name.to_owned()
+        } else {
+            metadata.titles.file_name(&sbf.name, ident, name)
+        }
+    }
+}
+
+// the build key of a track: how it's exported, its tags and the segments it's made of
+fn track_key<'a>(options: &ExportOptions, tags: &TrackMetadata, sbf: &SBF, entries: impl IntoIterator<Item = &'a SBFIndexEntry>) -> BuildKey {
+    let mut key = BuildKey::default();
+    key.add(format!("{options:?}")).add(format!("{tags:?}"));
+    for e in entries {
+        key.add(sbf.segment_data(e));
+    }
+    key
+}
+
+// moves the tracks that were exported into place, failed ones keep their earlier output; errors
+// if any failed
+fn commit(
+    output: &mut OutputDir,
+    plan: &mut Plan,
+    files: &[Vec<OutputFile>],
+    failed: &[usize],
+    remove_stale: bool,
+    batch: &Batch,
+) -> anyhow::Result<()> {
+    for &idx in failed {
+        plan.abandon(&files[idx]);
+    }
+    // a stale file may be the earlier output of a track that failed under a new name
+    output.commit(plan, remove_stale && failed.is_empty())?;
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let names = failed.iter().map(|&idx| batch.name(idx)).collect::<Vec<_>>();
+    Err(anyhow!("{} of {} tracks failed: {} (see above)", failed.len(), files.len(), names.join(", ")))
+}
+
+// --dry-run: what a run would do to the output directory
+fn print_plan(output: &OutputDir, plan: &Plan) -> anyhow::Result<ExitCode> {
+    let mut out = std::io::stdout().lock();
+    for (file, action) in &plan.actions {
+        writeln!(out, "{action:<10}{}", output.dir().join(file).display())?;
+    }
+    let counts = [Action::Write, Action::Keep, Action::Replace, Action::Overwrite, Action::Skip, Action::Remove]
+        .into_iter()
+        .map(|action| format!("{} {action}", plan.count(action)))
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", counts.join(", "))?;
+    plan.check(output.dir())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+    #[command(flatten)]
+    export: ExportArgs,
+    /// Join the segments of every track in SBF order (otherwise only the segments are written)
+    #[arg(long)]
+    group_by_prefix: bool,
+}
+
+#[derive(Args)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    export: ExportArgs,
+    /// Arrangements to render
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+}
+
+pub fn extract(args: ExtractArgs) -> anyhow::Result<ExitCode> {
+    let export = &args.export;
+    export.thread_pool()?;
+    let mut options = export.options();
+    if !args.group_by_prefix {
+        options.segment_files = match export.segments {
+            None | Some(SegmentFiles::Only) => SegmentFiles::Only,
+            Some(_) => return Err(anyhow!("extract writes only segments without --group-by-prefix, --segments has to be only")),
+        };
+    }
+    let filter = &export.tracks.filter();
+    let discovery = export.game.discover()?;
+    let mut code = ExitCode::SUCCESS;
+    // a broken file doesn't stop the others; every file is loaded so output names don't depend on
+    // the selection
+    let sbfs = discovery
+        .music_files()?
+        .iter()
+        .filter_map(|f| {
+            SBF::from_file(&f.path)
+                .inspect_err(|e| {
+                    eprintln!("error: {e:#}");
+                    if filter.selects_file(f.name()) {
+                        code = ExitCode::FAILURE;
+                    }
+                })
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    let (metadata, modified) = export.metadata(&sbfs)?;
+    let mut tracks = sbfs
+        .iter()
+        .flat_map(|sbf| {
+            let sbfs = &sbfs;
+            sbf.grouped_chunks.iter().map(move |(prefix, es)| {
+                // named after the file as well where more than one SBF has the prefix
+                let collides = sbfs.iter().filter(|s| s.grouped_chunks.contains_key(prefix)).count() > 1;
+                let name = filter.output_name(&output_name(&sbf.name, prefix, collides));
+                let es = filter.segments(&sbf.name, prefix, es).into_iter().cloned().collect::<Vec<_>>();
+                (sbf, prefix.as_str(), es, name)
+            })
+        })
+        .filter(|(_, _, es, _)| !es.is_empty())
+        .collect::<Vec<_>>();
+    if tracks.is_empty() {
+        return Err(anyhow!("no tracks selected"));
+    }
+    tracks.sort_by(|(_, a, _, a_name), (_, b, _, b_name)| (a, a_name).cmp(&(b, b_name)));
+    let files = tracks
+        .iter()
+        .map(|(sbf, prefix, es, name)| {
+            let file_name = export.file_name(&metadata, sbf, prefix, name);
+            let suffixes = es.iter().map(|e| e.suffix.clone()).collect::<Vec<_>>();
+            let mut files = options.output_files(name, &file_name, &sbf.name, prefix, &suffixes);
+            let tags = metadata.resolve(&sbf.name, prefix, es.iter().map(|e| format!("{}{}", e.ident, e.suffix)).collect());
+            let key = track_key(&options, &tags, sbf, es.iter()).finish();
+            files.iter_mut().for_each(|f| f.key = key.clone());
+            files
+        })
+        .collect::<Vec<_>>();
+
+    let mut output = export.output()?;
+    let mut plan = output.plan(files.iter().flatten().cloned())?;
+    if !filter.is_empty() {
+        plan.keep_stale();
+    }
+    if export.dry_run {
+        return print_plan(&output, &plan);
+    }
+    output.stage(&plan)?;
+    let batch = Batch::new(
+        tracks
+            .iter()
+            .zip(&files)
+            .map(|((sbf, _, es, name), files)| Track {
+                name: name.clone(),
+                export: plan.writes_any(files),
+                bytes: es.iter().map(|e| e.size as u64).sum(),
+                warnings: modified.get(&sbf.name).cloned().into_iter().collect(),
+            })
+            .collect(),
+    );
+    // a track that fails doesn't stop the others
+    let failed = (0..tracks.len())
+        .into_par_iter()
+        .filter(|&idx| plan.writes_any(&files[idx]))
+        .filter(|&idx| {
+            let (sbf, prefix, es, name) = &tracks[idx];
+            let start = Instant::now();
+            let result = export_prefix(sbf, prefix, es, name, output.staging(), &metadata, &options);
+            batch.done(idx, start.elapsed(), &result);
+            result.is_err()
+        })
+        .collect::<Vec<_>>();
+    batch.finish();
+    // outputs of files that didn't load stay
+    commit(&mut output, &mut plan, &files, &failed, code == ExitCode::SUCCESS, &batch)?;
+    Ok(code)
+}
+
+pub fn render(args: RenderArgs) -> anyhow::Result<ExitCode> {
+    let export = &args.export;
+    export.thread_pool()?;
+    let options = export.options();
+    let format = options.format;
+    let tracks_config = ArrangementConfig::load(&args.config)?;
+    let sbfs = export.game.load()?;
+    let (metadata, modified) = export.metadata(&sbfs)?;
+    let problems = validate(&tracks_config, &sbfs);
+    for problem in problems.iter().filter(|p| matches!(p, Problem::AmbiguousTrack { .. })) {
+        eprintln!("warning: {problem}");
+    }
+    // without the key in front, the batch summary shows it already
+    let errors = |key: &str| {
+        problems.iter()
+            .filter(|p| p.is_error() && p.concerns(key))
+            .map(|p| {
+                let problem = p.to_string();
+                problem.strip_prefix(&format!("{key}: ")).unwrap_or(&problem).to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+    let tracks = tracks_config.resolve_all(&sbfs);
+    let filter = export.tracks.filter();
+    // names over all tracks, so they don't change with the selection
+    let names = output_names(&tracks).iter().map(|name| filter.output_name(name)).collect::<Vec<_>>();
+    // config entries that can't be rendered fail on their own, the other tracks are still exported
+    let broken = tracks_config.tracks.iter()
+        .filter(|(key, arrangement)| {
+            let (file, ident) = split_track_key(key);
+            let file = tracks.iter().find(|t| t.key == key.as_str()).map_or(file.unwrap_or_default(), |t| t.sbf.name.as_str());
+            filter.arrangement(file, ident, arrangement).is_some()
+        })
+        .filter_map(|(key, _)| {
+            let errors = errors(key);
+            (!errors.is_empty()).then(|| (key.as_str(), errors.join("; ")))
+        })
+        .collect::<Vec<_>>();
+    let arrangements = tracks.iter()
+        .map(|track| filter.arrangement(&track.sbf.name, track.ident, track.arrangement))
+        .collect::<Vec<_>>();
+    let (tracks, names): (Vec<_>, Vec<_>) = tracks.into_iter().zip(names).zip(&arrangements)
+        .filter(|((track, _), _)| errors(track.key).is_empty())
+        .filter_map(|((track, name), arrangement)| {
+            let arrangement: &TrackArrangement = arrangement.as_ref()?;
+            Some((ResolvedTrack { arrangement, ..track }, name))
+        })
+        .unzip();
+    if tracks.is_empty() && broken.is_empty() {
+        return Err(anyhow!("no tracks selected"));
+    }
+    let file_names = tracks.iter().zip(&names)
+        .map(|(track, name)| export.file_name(&metadata, track.sbf, track.ident, name))
+        .collect::<Vec<_>>();
+    let files = tracks.iter().zip(names.iter().zip(&file_names))
+        .map(|(track, (name, file_name))| {
+            let order = &track.arrangement.order;
+            let mut files = options.output_files(name, file_name, &track.sbf.name, track.ident, order);
+            let segments = order.iter().map(|suffix| format!("{}{suffix}", track.ident)).collect();
+            let tags = metadata.resolve(&track.sbf.name, track.ident, segments);
+            let entries = order.iter().filter_map(|suffix| track.sbf.segment(track.ident, suffix));
+            let mut key = track_key(&options, &tags, track.sbf, entries);
+            key.add(format!("{:?}", track.arrangement.join_settings(&tracks_config.defaults)));
+            let key = key.finish();
+            files.iter_mut().for_each(|f| f.key = key.clone());
+            files
+        })
+        .chain(broken.iter().map(|_| vec![]))
+        .collect::<Vec<_>>();
+
+    let mut output = export.output()?;
+    output.record_config(&args.config)?;
+    let mut plan = output.plan(files.iter().flatten().cloned())?;
+    if !filter.is_empty() {
+        plan.keep_stale();
+    }
+    if export.dry_run {
+        for (key, error) in &broken {
+            eprintln!("error: {key}: {error}");
+        }
+        let code = print_plan(&output, &plan)?;
+        return Ok(if broken.is_empty() { code } else { ExitCode::FAILURE });
+    }
+    output.stage(&plan)?;
+    let batch = Batch::new(tracks.iter().zip(&files)
+        .map(|(track, files)| {
+            let mut warnings = problems.iter()
+                .filter_map(|p| match p {
+                    Problem::AmbiguousTrack { track: key, files } if *key == track.key => {
+                        Some(format!("also in {}", files[1..].join(", ")))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            warnings.extend(modified.get(&track.sbf.name).cloned());
+            Track {
+                name: track.key.to_owned(),
+                export: plan.writes_any(files),
+                bytes: track.arrangement.order.iter()
+                    .filter_map(|suffix| track.sbf.segment(track.ident, suffix))
+                    .map(|e| e.size as u64)
+                    .sum(),
+                warnings,
+            }
+        })
+        .chain(broken.iter().map(|(key, _)| Track { name: key.to_string(), export: true, bytes: 0, warnings: vec![] }))
+        .collect());
+    for (idx, (_, error)) in (tracks.len()..).zip(&broken) {
+        batch.done(idx, Duration::ZERO, &Err(anyhow!("{error}")));
+    }
+    let staged = output.staging().join(format.extension());
+    let render_track = |track: &ResolvedTrack, name: &str, file_name: &str| -> anyhow::Result<()> {
+        if options.segment_files.segments() {
+            let entries = track.arrangement.order.iter()
+                .filter_map(|suffix| track.sbf.segment(track.ident, suffix))
+                .collect::<Vec<_>>();
+            export_segments(track.sbf, track.ident, &entries, &staged.join(name), &metadata, &options)?;
+        }
+        if !options.segment_files.tracks() {
+            return Ok(());
+        }
+        let path = staged.join(format!("{file_name}.{}", format.extension()));
+        let segments = track.arrangement.order.iter().map(|suffix| format!("{}{suffix}", track.ident)).collect();
+        let info = TrackInfo {
+            metadata: metadata.resolve(&track.sbf.name, track.ident, segments),
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            frames: 0,
+            sample_format: options.sample_format,
+            markers: vec![],
+        };
+        match &options.decoder {
+            DecodeMode::Classic => {
+                let joined = tracks_config.render(track.sbf, track.ident, track.arrangement)?;
+                options.write_joined(&path, info, &joined)
+            }
+            DecodeMode::Enhanced(settings) => {
+                let joined = tracks_config.render_enhanced(track.sbf, track.ident, track.arrangement, settings)?;
+                options.write_joined(&path, info, &joined)
+            }
+        }
+    };
+    // a track that fails doesn't stop the others
+    let mut failed = (0..tracks.len()).into_par_iter()
+        .filter(|&idx| plan.writes_any(&files[idx]))
+        .filter(|&idx| {
+            let start = Instant::now();
+            let result = render_track(&tracks[idx], &names[idx], &file_names[idx]);
+            batch.done(idx, start.elapsed(), &result);
+            result.is_err()
+        })
+        .collect::<Vec<_>>();
+    failed.extend(tracks.len()..files.len());
+    batch.finish();
+    commit(&mut output, &mut plan, &files, &failed, true, &batch)?;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,96 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Args;
+use glob::Pattern;
+use dfbhd_mus::discover::{discover, Discovery, FileFilter};
+use dfbhd_mus::sbf::SBF;
+use dfbhd_mus::select::{TrackFilter, TrackPattern};
+
+/// Where the SBFs come from, shared by every command reading the game files.
+#[derive(Args)]
+pub struct GameArgs {
+    /// Game directory, searched recursively for SBF files
+    #[arg(short, long)]
+    pub game_dir: PathBuf,
+    #[command(flatten)]
+    pub files: FileArgs,
+}
+
+/// Which files of the game dir are used, for commands where the game dir is optional.
+#[derive(Args)]
+pub struct FileArgs {
+    /// Also use files matching this glob whatever their content (repeatable)
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    include: Vec<Pattern>,
+    /// Ignore files matching this glob (repeatable); tracks are left out with --exclude-track
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    exclude: Vec<Pattern>,
+}
+
+impl FileArgs {
+    pub fn discover(&self, game_dir: &Path) -> anyhow::Result<Discovery> {
+        let filter = FileFilter {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+        };
+        discover(game_dir, &filter)
+    }
+}
+
+impl GameArgs {
+    pub fn discover(&self) -> anyhow::Result<Discovery> {
+        self.files.discover(&self.game_dir)
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<SBF>> {
+        self.discover()?.load()
+    }
+}
+
+/// Which tracks to use, shared by `list`, `extract` and `render`.
+#[derive(Args)]
+pub struct TrackArgs {
+    /// Only tracks matching this glob, or regex written as /.../ (repeatable); `EXP1.sbf:m*` matches qualified names
+    #[arg(long, value_name = "PATTERN", value_parser = TrackPattern::parse)]
+    track: Vec<TrackPattern>,
+    /// Leave out tracks matching this glob or /regex/ (repeatable)
+    #[arg(long, value_name = "PATTERN", value_parser = TrackPattern::parse)]
+    exclude_track: Vec<TrackPattern>,
+    /// Only tracks from SBF files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    file: Vec<Pattern>,
+    /// Only segments whose suffix starts with this, e.g. a020 for a tier of the mission music (repeatable); written as
+    /// <name>.<tier>
+    #[arg(long)]
+    tier: Vec<String>,
+}
+
+impl TrackArgs {
+    pub fn filter(&self) -> TrackFilter {
+        TrackFilter {
+            tracks: self.track.clone(),
+            exclude: self.exclude_track.clone(),
+            files: self.file.clone(),
+            tiers: self.tier.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    game: GameArgs,
+}
+
+pub fn scan(args: ScanArgs) -> anyhow::Result<ExitCode> {
+    let discovery = args.game.discover()?;
+    let mut out = std::io::stdout().lock();
+    for file in &discovery.files {
+        writeln!(out, "{:<40} {}", file.relative, file.status)?;
+    }
+    let used = discovery.selected().count();
+    writeln!(out, "{} SBF files found, {used} used", discovery.files.len())?;
+    Ok(if used > 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
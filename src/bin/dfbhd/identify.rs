@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::anyhow;
+use clap::Args;
+use dfbhd_mus::fingerprint::{Fingerprint, FingerprintDatabase, Identification, Release, DEFAULT_FINGERPRINTS_PATH};
+use crate::game::GameArgs;
+
+#[derive(Args)]
+pub struct IdentifyArgs {
+    #[command(flatten)]
+    game: GameArgs,
+    /// Known releases, recorded with `--add`
+    #[arg(long, default_value = DEFAULT_FINGERPRINTS_PATH)]
+    fingerprints: PathBuf,
+    /// Record the files as the release given by --game and --version
+    #[arg(long, requires_all = ["game_name", "version"])]
+    add: bool,
+    /// Game of the release to record, e.g. "Delta Force: Black Hawk Down"
+    #[arg(long = "game", value_name = "GAME")]
+    game_name: Option<String>,
+    /// Version of the release to record, e.g. "1.5.0.5"
+    #[arg(long)]
+    version: Option<String>,
+    /// Album for tracks from this release (default: derived from the file)
+    #[arg(long)]
+    album: Option<String>,
+    /// Year for tracks from this release
+    #[arg(long)]
+    year: Option<String>,
+}
+
+// "2 changed (m3a010a, m3a010b)"
+fn describe(what: &str, names: &[String]) -> Option<String> {
+    (!names.is_empty()).then(|| format!("{} {what} ({})", names.len(), names.join(", ")))
+}
+
+pub fn identify(args: IdentifyArgs) -> anyhow::Result<ExitCode> {
+    let sbfs = args.game.load()?;
+    let fingerprints = sbfs.iter().map(Fingerprint::of).collect::<Vec<_>>();
+    if args.add {
+        let (Some(game), Some(version)) = (&args.game_name, &args.version) else {
+            return Err(anyhow!("--add needs --game and --version"));
+        };
+        let mut db = FingerprintDatabase::load(&args.fingerprints)?;
+        for (sbf, fingerprint) in sbfs.iter().zip(fingerprints) {
+            // re-adding a release replaces its files
+            db.releases.retain(|r| !(&r.game == game && &r.version == version && r.file.eq_ignore_ascii_case(&sbf.name)));
+            db.releases.push(Release {
+                game: game.to_owned(),
+                version: version.to_owned(),
+                album: args.album.clone(),
+                year: args.year.clone(),
+                file: sbf.name.clone(),
+                fingerprint,
+            });
+        }
+        db.save(&args.fingerprints)?;
+        eprintln!("added {} files of {game} {version} to {:?}", sbfs.len(), args.fingerprints);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let db = FingerprintDatabase::load(&args.fingerprints)?;
+    if db.releases.is_empty() {
+        eprintln!("no releases recorded in {:?}, every file is unknown (record files with --add)", args.fingerprints);
+    }
+    let mut out = std::io::stdout().lock();
+    for (sbf, fingerprint) in sbfs.iter().zip(&fingerprints) {
+        match db.identify(&sbf.name, fingerprint) {
+            Identification::Known(release) => writeln!(out, "{:<16}{}", sbf.name, release.name())?,
+            Identification::Modified { release, changed, added, removed } => {
+                let changes = [describe("changed", &changed), describe("added", &added), describe("removed", &removed)]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                let changes = if changes.is_empty() { "same segments, repacked".to_owned() } else { changes.join(", ") };
+                writeln!(out, "{:<16}modified {} {}: {changes}", sbf.name, release.name(), release.file)?;
+            }
+            Identification::Unknown => writeln!(out, "{:<16}unknown", sbf.name)?,
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
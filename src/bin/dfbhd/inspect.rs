@@ -0,0 +1,193 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Args;
+use itertools::Itertools;
+use serde_json::json;
+use dfbhd_mus::arrangement::find_track;
+use dfbhd_mus::mix::{format_time, frames_to_duration};
+use dfbhd_mus::sbf::{SBFIndexEntry, CHANNELS, SBF};
+use dfbhd_mus::select::TrackFilter;
+use dfbhd_mus::titles::{TitleDatabase, DEFAULT_TITLES_PATH};
+use crate::game::{GameArgs, TrackArgs};
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    game: GameArgs,
+    #[command(flatten)]
+    tracks: TrackArgs,
+    /// Title overrides
+    #[arg(long, default_value = DEFAULT_TITLES_PATH)]
+    titles: PathBuf,
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// SBF file
+    sbf: PathBuf,
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Track name, bare (`m3`) or qualified (`EXP1.sbf:m3`)
+    track: String,
+    #[command(flatten)]
+    game: GameArgs,
+    /// Show every block of every segment
+    #[arg(long)]
+    blocks: bool,
+}
+
+// selected idents in index order, with their selected segments
+fn tracks<'a>(sbf: &'a SBF, filter: &TrackFilter) -> Vec<(&'a str, Vec<&'a SBFIndexEntry>)> {
+    sbf.chunks
+        .iter()
+        .map(|e| e.ident.as_str())
+        .unique()
+        .map(|ident| (ident, filter.segments(&sbf.name, ident, &sbf.grouped_chunks[ident])))
+        .filter(|(_, segments)| !segments.is_empty())
+        .collect()
+}
+
+// length of segments of a track, from the block headers
+fn track_frames(sbf: &SBF, segments: &[&SBFIndexEntry]) -> u64 {
+    segments.iter().map(|e| sbf.segment_frames(e)).sum()
+}
+
+pub fn list(args: ListArgs) -> anyhow::Result<ExitCode> {
+    let filter = args.tracks.filter();
+    let sbfs = args.game.load()?;
+    let titles = TitleDatabase::load(&args.titles)?;
+    // files without any selected track are left out when filtering
+    let files = sbfs
+        .iter()
+        .map(|sbf| (sbf, tracks(sbf, &filter)))
+        .filter(|(_, tracks)| filter.is_empty() || !tracks.is_empty())
+        .collect::<Vec<_>>();
+    let mut out = std::io::stdout().lock();
+    if args.json {
+        let files = files
+            .iter()
+            .map(|(sbf, tracks)| {
+                let tracks = tracks
+                    .iter()
+                    .map(|(ident, segments)| {
+                        let frames = track_frames(sbf, segments);
+                        json!({
+                            "ident": ident,
+                            "title": titles.title(&sbf.name, ident),
+                            "segments": segments.len(),
+                            "frames": frames,
+                            "duration": frames_to_duration(frames as usize).as_secs_f64(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "file": sbf.name, "tracks": tracks })
+            })
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", serde_json::to_string_pretty(&files)?)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    for (sbf, tracks) in &files {
+        writeln!(out, "{}", sbf.name)?;
+        writeln!(out, "  {:<10}{:>9}{:>12}  title", "track", "segments", "duration")?;
+        for (ident, segments) in tracks {
+            let duration = format_time(frames_to_duration(track_frames(sbf, segments) as usize));
+            let title = titles.title(&sbf.name, ident).unwrap_or_default();
+            let line = format!("  {ident:<10}{:>9}{duration:>12}  {title}", segments.len());
+            writeln!(out, "{}", line.trim_end())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn info(args: InfoArgs) -> anyhow::Result<ExitCode> {
+    let sbf = SBF::from_file(&args.sbf)?;
+    let header = &sbf.header;
+    let magic = String::from_utf8_lossy(&header.magic);
+    let mut out = std::io::stdout().lock();
+    if args.json {
+        let index = sbf
+            .chunks
+            .iter()
+            .map(|e| {
+                json!({
+                    "ident": e.ident,
+                    "suffix": e.suffix,
+                    "start": e.start,
+                    "size": e.size,
+                    "blocks": e.size / e.block_size,
+                })
+            })
+            .collect::<Vec<_>>();
+        let info = json!({
+            "file": sbf.name,
+            "size": sbf.content.len(),
+            "header": {
+                "magic": magic,
+                "i1": header.i1,
+                "i2": header.i2,
+                "i3": header.i3,
+                "index_offset": header.index_offset,
+                "index_count": header.index_count,
+            },
+            "index": index,
+        });
+        writeln!(out, "{}", serde_json::to_string_pretty(&info)?)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    writeln!(out, "file:         {} ({} bytes)", sbf.name, sbf.content.len())?;
+    writeln!(out, "magic:        {magic:?}")?;
+    writeln!(out, "i1, i2, i3:   {}, {}, {}", header.i1, header.i2, header.i3)?;
+    writeln!(out, "index offset: {}", header.index_offset)?;
+    writeln!(out, "index count:  {}", header.index_count)?;
+    writeln!(out)?;
+    writeln!(out, "{:<10}{:<10}{:>10}{:>10}{:>8}", "ident", "suffix", "start", "size", "blocks")?;
+    for e in &sbf.chunks {
+        writeln!(out, "{:<10}{:<10}{:>10}{:>10}{:>8}", e.ident, e.suffix, e.start, e.size, e.size / e.block_size)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn inspect(args: InspectArgs) -> anyhow::Result<ExitCode> {
+    let sbfs = args.game.load()?;
+    let (sbf, ident) = find_track(&sbfs, &args.track)?;
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{}:{ident}", sbf.name)?;
+    writeln!(out, "{:<10}{:>8}{:>10}{:>10}{:>8}", "segment", "blocks", "frames", "seconds", "scale")?;
+    for e in &sbf.grouped_chunks[ident] {
+        let blocks = sbf.segment_blocks(e);
+        let frames = sbf.segment_frames(e);
+        let scales = match blocks.iter().map(|b| b.scale1).minmax().into_option() {
+            Some((min, max)) if min != max => format!("{min}-{max}"),
+            Some((min, _)) => min.to_string(),
+            None => "-".to_owned(),
+        };
+        let seconds = frames_to_duration(frames as usize).as_secs_f64();
+        writeln!(out, "{:<10}{:>8}{frames:>10}{seconds:>10.3}{scales:>8}", e.suffix, blocks.len())?;
+        if !args.blocks {
+            continue;
+        }
+        for (idx, block) in blocks.iter().enumerate() {
+            // largest distance from silence, in 8-bit steps
+            let peak = block.content[0..block.size as usize].iter().map(|&b| (b as i16 - 128).unsigned_abs()).max().unwrap_or(0);
+            writeln!(
+                out,
+                "  block {idx:<4} size {:>5} ({:>4} frames)  scale1 {:>2}  scale2 {:>2}  peak {peak:>3}",
+                block.size,
+                block.size / CHANNELS as u32,
+                block.scale1,
+                block.scale2,
+            )?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
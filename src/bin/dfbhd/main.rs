@@ -0,0 +1,79 @@
+use std::io::ErrorKind;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+
+mod cat;
+mod config;
+mod export;
+mod game;
+mod identify;
+mod inspect;
+mod play;
+mod progress;
+mod validate;
+mod verify;
+
+/// Decodes and arranges the music of Delta Force: Black Hawk Down (SBF files).
+#[derive(Parser)]
+#[command(name = "dfbhd", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show which SBF files in the game dir are used
+    Scan(game::ScanArgs),
+    /// List the tracks of every music SBF
+    List(inspect::ListArgs),
+    /// Show the header and index of an SBF file
+    Info(inspect::InfoArgs),
+    /// Identify the game release of every music SBF, or record a release
+    Identify(identify::IdentifyArgs),
+    /// Export tracks as stored, grouped by prefix or as single segments
+    Extract(export::ExtractArgs),
+    /// Export tracks arranged as configured in the reordering config
+    Render(export::RenderArgs),
+    /// Check exported files against the checksums in their manifest.json
+    Verify(verify::VerifyArgs),
+    /// Interactively reorder the segments of the tracks in an SBF file
+    Play(play::PlayArgs),
+    /// Check the reordering config against the SBFs
+    Validate(validate::ValidateArgs),
+    /// Show the segments and blocks of a track
+    Inspect(inspect::InspectArgs),
+    /// Decode a track to stdout as WAV or raw PCM
+    Cat(cat::CatArgs),
+    /// Diff, merge, export and import arrangements
+    #[command(subcommand)]
+    Config(config::ConfigCommand),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Scan(args) => game::scan(args),
+        Command::List(args) => inspect::list(args),
+        Command::Info(args) => inspect::info(args),
+        Command::Identify(args) => identify::identify(args),
+        Command::Extract(args) => export::extract(args),
+        Command::Render(args) => export::render(args),
+        Command::Verify(args) => verify::verify(args),
+        Command::Play(args) => play::play(args),
+        Command::Validate(args) => validate::validate(args),
+        Command::Inspect(args) => inspect::inspect(args),
+        Command::Cat(args) => cat::cat(args),
+        Command::Config(command) => config::config(command),
+    };
+    match result {
+        Ok(code) => code,
+        // the reader went away (`| head`, a closed player), that's not an error for us
+        Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::BrokenPipe) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
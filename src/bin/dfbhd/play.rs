@@ -1,21 +1,50 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::time::Duration;
+use anyhow::Context;
+use clap::Args;
 use rodio::{OutputStream, Sink, Source};
 use rodio::source::SeekError;
-use dfbhd_mus::sbf::{upscale_pcm, SBFChunkData, SBFIndexEntry, SBF};
+use dfbhd_mus::arrangement::{qualified_key, ArrangementConfig, TrackArrangement, DEFAULT_CONFIG_PATH};
+use dfbhd_mus::interchange::{export, timings, Format};
+use dfbhd_mus::mix::Joined;
+use dfbhd_mus::sbf::{CHANNELS, SAMPLE_RATE, SBF};
+use dfbhd_mus::titles::{TitleDatabase, DEFAULT_TITLES_PATH};
 use ncurses::*;
-use dfbhd_mus::{array_transmute, log};
 
-fn main() {
-    let mut args = std::env::args();
-    args.next();
-    let config_path = "reordering_config.json";
-    let mut config = serde_json::from_str::<PlayerConfig>(&std::fs::read_to_string(&config_path).unwrap_or("{}".to_string())).unwrap();
-    let sbf = SBF::from_file(PathBuf::from(args.next().unwrap()).as_path()).unwrap();
+const EXPORT_DIR: &str = "arrangements";
+
+#[derive(Args)]
+pub struct PlayArgs {
+    /// SBF file to edit
+    sbf: PathBuf,
+    /// Arrangements are loaded from and saved to this file
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Title overrides
+    #[arg(long, default_value = DEFAULT_TITLES_PATH)]
+    titles: PathBuf,
+}
+
+pub fn play(args: PlayArgs) -> anyhow::Result<ExitCode> {
+    let config_path = args.config.as_path();
+    let mut config = ArrangementConfig::load(config_path)?;
+    let sbf = SBF::from_file(&args.sbf)?;
     let mut tracks = sbf.grouped_chunks.keys().collect::<Vec<_>>();
     tracks.sort();
+    let titles = TitleDatabase::load(&args.titles)?;
+    let labels = tracks
+        .iter()
+        .map(|&track| match titles.get(&sbf.name, track) {
+            Some(t) => {
+                let summary = t.summary();
+                let title = t.title.unwrap_or_default();
+                if summary.is_empty() { format!("{track:<10}{title}") } else { format!("{track:<10}{title} ({summary})") }
+            }
+            None => track.to_owned(),
+        })
+        .collect::<Vec<_>>();
     nc_init();
 
     let mut screen = 0;
@@ -24,12 +53,17 @@ fn main() {
     loop {
         match screen {
             0 => {
-                track_name = select_track(&tracks, &mut selected_item);
+                select_track(&labels, &mut selected_item);
+                track_name = tracks[selected_item].to_owned();
                 screen = 1;
             }
             1 => {
-                edit_config(&mut config, &sbf, &track_name);
-                std::fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
+                let edited = edit_config(&mut config, &sbf, &track_name);
+                if let Err(e) = edited.and_then(|()| config.save(config_path)) {
+                    // leave curses mode so the error is readable
+                    endwin();
+                    return Err(e);
+                }
                 screen = 0;
             }
             _ => panic!("boo"),
@@ -37,7 +71,7 @@ fn main() {
     }
 }
 
-fn select_track(options: &[&String], selected_item: &mut usize) -> String {
+fn select_track(options: &[String], selected_item: &mut usize) {
     loop {
         clear();
         for (idx, item) in options.iter().enumerate() {
@@ -57,58 +91,46 @@ fn select_track(options: &[&String], selected_item: &mut usize) -> String {
         } else if ch == KEY_DOWN && *selected_item != options.len() - 1 {
             *selected_item += 1;
         } else if ch == KEY_RIGHT {
-            return options.get(*selected_item).unwrap().to_string();
+            return;
         }
     }
 }
 
-fn edit_config(config: &mut PlayerConfig, sbf: &SBF, track_name: &str) {
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let sink = Sink::try_new(&stream_handle).unwrap();
+fn edit_config(config: &mut ArrangementConfig, sbf: &SBF, track_name: &str) -> anyhow::Result<()> {
+    let (_stream, stream_handle) = OutputStream::try_default().context("no audio output device")?;
+    let sink = Sink::try_new(&stream_handle).context("couldn't open the audio output")?;
     sink.pause();
 
     let mut track = sbf.grouped_chunks.get(track_name).unwrap().clone();
-    if !config.contains_key(track_name) {
-        config.insert(track_name.to_owned(), track.iter().map(|ie| ie.suffix.to_owned()).collect());
-    }
-    let track_config = config.get(track_name).unwrap();
+    let (loaded_key, mut arrangement) = match config.find_for(sbf, track_name) {
+        Some((key, arrangement)) => (Some(key.to_owned()), arrangement.clone()),
+        None => (None, TrackArrangement::new(track.iter().map(|ie| ie.suffix.to_owned()).collect())),
+    };
     track.sort_by_key(|ie|
-            track_config.iter().enumerate()
+            arrangement.order.iter().enumerate()
                 .find(|(_, suffix)| *suffix == &ie.suffix )
-                .unwrap()
-                .0
+                .map(|(idx, _)| idx)
+                .unwrap_or(usize::MAX)
         );
     #[derive(Default)]
     struct ReorderedData {
-        chunk_pcms: Vec<Vec<i16>>,
+        joined: Joined,
         chunk_offsets: Vec<Duration>,
     }
     impl ReorderedData {
-        fn recalc(&mut self, track: &[SBFIndexEntry], sbf: &SBF) {
-            self.chunk_pcms.clear();
-            self.chunk_offsets.clear();
-            let mut curr_chunk_offset = Duration::ZERO;
-            for chunk in track.iter() {
-                self.chunk_offsets.push(curr_chunk_offset);
-                let mut pcm_data = Vec::with_capacity(1024 * 1024);
-                let segment_blob = &sbf.content[chunk.start as usize..(chunk.start + chunk.size) as usize];
-                for chunk in array_transmute::<_, SBFChunkData>(segment_blob) {
-                    for &b in &chunk.content[0..chunk.size as usize] {
-                        let sample = upscale_pcm(b, chunk.scale1);
-                        pcm_data.push(sample);
-                    }
-                }
-                let chunk_duration_sex = 1.0 / 22050.0 * (pcm_data.len() / 2) as f64;
-                let chunk_duration = Duration::from_secs_f64(chunk_duration_sex);
-                curr_chunk_offset += chunk_duration;
-                self.chunk_pcms.push(pcm_data);
-            }
+        fn recalc(&mut self, config: &ArrangementConfig, arrangement: &TrackArrangement, sbf: &SBF, track_name: &str) {
+            self.joined = config.render(sbf, track_name, arrangement).unwrap();
+            self.chunk_offsets = (0..self.joined.offsets.len())
+                .map(|idx| self.joined.offset_duration(idx))
+                .collect();
         }
     }
     let mut rd = ReorderedData::default();
     let mut rd_dirty = true;
     let mut selected_chunk = 0;
     let mut playing_chunk;
+    // result of the last export, shown below the segments
+    let mut status = String::new();
     loop {
         playing_chunk = usize::MAX;
         if !sink.is_paused() {
@@ -132,9 +154,12 @@ fn edit_config(config: &mut PlayerConfig, sbf: &SBF, track_name: &str) {
             };
             attron(COLOR_PAIR(attr_idx));
             let chunk = track.get(idx).unwrap();
-            mvprintw(idx as _, 0, &format!("{}{}", chunk.ident, chunk.suffix)).unwrap();
+            mvprintw(idx as _, 0, format!("{}{}", chunk.ident, chunk.suffix).as_str()).unwrap();
             attroff(COLOR_PAIR(attr_idx));
         }
+        if !status.is_empty() {
+            mvprintw(track.len() as i32 + 1, 0, &status).unwrap();
+        }
         refresh();
 
         let ch = getch();
@@ -158,17 +183,14 @@ fn edit_config(config: &mut PlayerConfig, sbf: &SBF, track_name: &str) {
             rd_dirty = true;
         } else if ch == KEY_RIGHT {
             if rd_dirty {
-                rd.recalc(&track, &sbf);
+                arrangement.order = track.iter().map(|ie| ie.suffix.to_owned()).collect();
+                rd.recalc(config, &arrangement, sbf, track_name);
                 rd_dirty = false;
             }
-            let mut pcm_data = Vec::<i16>::with_capacity(1024 * 1024);
-            for chunk_pcm in &rd.chunk_pcms {
-                pcm_data.extend(chunk_pcm);
-            }
             let source = RawPcmSource {
-                data: Arc::new(pcm_data),
-                channels: 2,
-                sample_rate: 22050,
+                data: Arc::new(rd.joined.samples.clone()),
+                channels: CHANNELS,
+                sample_rate: SAMPLE_RATE,
                 index: 0,
             };
 
@@ -182,25 +204,51 @@ fn edit_config(config: &mut PlayerConfig, sbf: &SBF, track_name: &str) {
             sink.play();
         } else if ch == KEY_LEFT {
             if sink.is_paused() {
-                config.insert(track_name.to_owned(), track.iter().map(|ie| ie.suffix.to_owned()).collect());
-                return;
+                arrangement.order = track.iter().map(|ie| ie.suffix.to_owned()).collect();
+                // a bare key is replaced by the qualified one, leaving both would configure the
+                // track twice
+                if let Some(key) = loaded_key {
+                    config.tracks.remove(&key);
+                }
+                config.tracks.insert(qualified_key(&sbf.name, track_name), arrangement);
+                return Ok(());
             } else {
                 sink.pause();
             }
         } else if ch == 'e' as i32 {
-            let repr = serde_json::to_string(
-                &track.iter()
-                    .map(|ie| format!("{}{}", ie.ident, ie.suffix))
-                    .collect::<Vec<_>>()
-            ).unwrap();
-            log(format!("let {}: [&str; {}] = {};", track_name, track.len(), repr));
+            if rd_dirty {
+                arrangement.order = track.iter().map(|ie| ie.suffix.to_owned()).collect();
+                rd.recalc(config, &arrangement, sbf, track_name);
+                rd_dirty = false;
+            }
+            status = match export_arrangement(sbf, track_name, &arrangement, &rd.joined) {
+                Ok(()) => format!("exported to {EXPORT_DIR}/"),
+                Err(e) => format!("export failed: {e:#}"),
+            };
         }
     }
 }
 
+// Writes the current arrangement in every interchange format to `arrangements/<sbf stem>_<track>.<ext>`.
+fn export_arrangement(sbf: &SBF, track_name: &str, arrangement: &TrackArrangement, joined: &Joined) -> anyhow::Result<()> {
+    let dir = Path::new(EXPORT_DIR);
+    std::fs::create_dir_all(dir).context(format!("couldn't create {dir:?}"))?;
+    let stem = sbf.name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&sbf.name);
+    let segment_timings = timings(joined);
+    for format in Format::ALL {
+        let exported = export(format, track_name, &arrangement.order, Some(&segment_timings))?;
+        let path = dir.join(format!("{stem}_{track_name}.{}", format.extension()));
+        std::fs::write(&path, exported).context(format!("couldn't write {path:?}"))?;
+    }
+    Ok(())
+}
+
 fn nc_init() {
-    std::panic::set_hook(Box::new(|info| {
-        log(format!("Panic occurred: {}", info));
+    // leave curses mode first so the message shows up on a usable terminal
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        endwin();
+        default_hook(info);
     }));
 
     initscr();               // Start curses mode
@@ -272,6 +320,4 @@ impl Source for RawPcmSource {
 
         Ok(())
     }
-}
-
-type PlayerConfig = HashMap<String, Vec<String>>;
\ No newline at end of file
+}
\ No newline at end of file
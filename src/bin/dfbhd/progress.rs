@@ -0,0 +1,141 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A track of a batch export.
+pub struct Track {
+    pub name: String,
+    // false when it's up to date
+    pub export: bool,
+    // SBF data decoded to export it
+    pub bytes: u64,
+    pub warnings: Vec<String>,
+}
+
+enum Outcome {
+    Pending,
+    UpToDate,
+    Exported(Duration),
+    Failed(Duration, String),
+}
+
+struct State {
+    outcomes: Vec<Outcome>,
+    done: usize,
+    bytes: u64,
+}
+
+/// Progress of a batch export on stderr, redrawn in place on a terminal and one line per track
+/// otherwise, followed by a summary of every track.
+pub struct Batch {
+    tracks: Vec<Track>,
+    tty: bool,
+    total: usize,
+    total_bytes: u64,
+    start: Instant,
+    state: Mutex<State>,
+}
+
+// "1.2 MB"
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+// "0:42"
+fn minutes(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+impl Batch {
+    pub fn new(tracks: Vec<Track>) -> Self {
+        let outcomes = tracks.iter().map(|t| if t.export { Outcome::Pending } else { Outcome::UpToDate }).collect();
+        let exported = tracks.iter().filter(|t| t.export);
+        Self {
+            tty: std::io::stderr().is_terminal(),
+            total: exported.clone().count(),
+            total_bytes: exported.map(|t| t.bytes).sum(),
+            start: Instant::now(),
+            state: Mutex::new(State { outcomes, done: 0, bytes: 0 }),
+            tracks,
+        }
+    }
+
+    pub fn name(&self, idx: usize) -> &str {
+        &self.tracks[idx].name
+    }
+
+    /// Track `idx` was exported (or not) in `time`.
+    pub fn done(&self, idx: usize, time: Duration, result: &anyhow::Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.done += 1;
+        state.bytes += self.tracks[idx].bytes;
+        state.outcomes[idx] = match result {
+            Ok(()) => Outcome::Exported(time),
+            Err(e) => Outcome::Failed(time, format!("{e:#}")),
+        };
+        let mut err = std::io::stderr().lock();
+        let counter = format!("[{}/{}]", state.done, self.total);
+        if self.tty {
+            let _ = write!(err, "\r\x1b[K");
+            if let Err(e) = result {
+                let _ = writeln!(err, "error: {}: {e:#}", self.tracks[idx].name);
+            }
+            // remaining data at the rate so far
+            let eta = match state.bytes {
+                0 => String::new(),
+                bytes => format!(", ETA {}", minutes(self.start.elapsed().mul_f64((self.total_bytes - bytes) as f64 / bytes as f64))),
+            };
+            let _ = write!(err, "{counter} {} of {} decoded{eta}", megabytes(state.bytes), megabytes(self.total_bytes));
+        } else {
+            let _ = match result {
+                Ok(()) => writeln!(err, "{counter} {} exported ({}, {:.1}s)", self.tracks[idx].name, megabytes(self.tracks[idx].bytes), time.as_secs_f64()),
+                Err(e) => writeln!(err, "{counter} error: {}: {e:#}", self.tracks[idx].name),
+            };
+        }
+        let _ = err.flush();
+    }
+
+    /// Prints the result of every track.
+    pub fn finish(&self) {
+        let state = self.state.lock().unwrap();
+        let mut err = std::io::stderr().lock();
+        if self.tty && self.total > 0 {
+            let _ = write!(err, "\r\x1b[K");
+        }
+        let width = self.tracks.iter().map(|t| t.name.len()).max().unwrap_or(0).max(5) + 2;
+        let _ = writeln!(err, "{:<width$}{:<12}{:>8}  notes", "track", "result", "time");
+        let (mut exported, mut up_to_date, mut failed, mut not_run, mut warned) = (0, 0, 0, 0, 0);
+        for (track, outcome) in self.tracks.iter().zip(&state.outcomes) {
+            let (result, time, mut notes) = match outcome {
+                Outcome::Pending => ("not run", None, vec![]),
+                Outcome::UpToDate => ("up to date", None, vec![]),
+                Outcome::Exported(time) => ("exported", Some(time), vec![]),
+                Outcome::Failed(time, e) => ("failed", Some(time), vec![e.clone()]),
+            };
+            match outcome {
+                Outcome::Exported(_) => exported += 1,
+                Outcome::UpToDate => up_to_date += 1,
+                Outcome::Failed(..) => failed += 1,
+                Outcome::Pending => not_run += 1,
+            }
+            if !track.warnings.is_empty() {
+                warned += 1;
+            }
+            notes.extend(track.warnings.iter().map(|w| format!("warning: {w}")));
+            let time = time.map_or(String::new(), |t| format!("{:.1}s", t.as_secs_f64()));
+            let line = format!("{:<width$}{result:<12}{time:>8}  {}", track.name, notes.join("; "));
+            let _ = writeln!(err, "{}", line.trim_end());
+        }
+        let not_run = match not_run {
+            0 => String::new(),
+            n => format!(", {n} not run"),
+        };
+        let _ = writeln!(
+            err,
+            "{} tracks: {exported} exported, {up_to_date} up to date, {failed} failed{not_run}, {warned} with warnings, {:.1}s",
+            self.tracks.len(),
+            self.start.elapsed().as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Args;
+use dfbhd_mus::arrangement::{ArrangementConfig, DEFAULT_CONFIG_PATH};
+use crate::game::GameArgs;
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    game: GameArgs,
+    /// Config to check
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Fail on warnings too
+    #[arg(long)]
+    strict: bool,
+}
+
+pub fn validate(args: ValidateArgs) -> anyhow::Result<ExitCode> {
+    let config = ArrangementConfig::load(&args.config)?;
+    let sbfs = args.game.load()?;
+
+    let problems = dfbhd_mus::validate::validate(&config, &sbfs);
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    for problem in &problems {
+        println!("{}: {problem}", if problem.is_error() { "error" } else { "warning" });
+    }
+    println!("{} tracks checked, {errors} errors, {} warnings", config.tracks.len(), problems.len() - errors);
+    if errors > 0 || (args.strict && !problems.is_empty()) {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,53 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::anyhow;
+use clap::Args;
+use dfbhd_mus::encoder::OutputFormat;
+use dfbhd_mus::output::{verify as verify_dir, Verified, OUTPUT_MANIFEST};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// A format directory with a manifest.json, or an output directory holding wav/ and flac/
+    dir: PathBuf,
+    /// List every file, not only the ones that don't match
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+pub fn verify(args: VerifyArgs) -> anyhow::Result<ExitCode> {
+    let dirs = if args.dir.join(OUTPUT_MANIFEST).exists() {
+        vec![args.dir.clone()]
+    } else {
+        [OutputFormat::Wav, OutputFormat::Flac]
+            .iter()
+            .map(|format| args.dir.join(format.extension()))
+            .filter(|dir| dir.join(OUTPUT_MANIFEST).exists())
+            .collect()
+    };
+    if dirs.is_empty() {
+        return Err(anyhow!("no {OUTPUT_MANIFEST} in {:?} or its wav/flac directories", args.dir));
+    }
+    let mut out = std::io::stdout().lock();
+    let mut code = ExitCode::SUCCESS;
+    for dir in dirs {
+        let files = verify_dir(&dir)?;
+        for (file, verified) in &files {
+            if args.verbose || *verified != Verified::Ok {
+                writeln!(out, "{verified:<10}{}", dir.join(file).display())?;
+            }
+        }
+        let count = |v: Verified| files.iter().filter(|(_, verified)| *verified == v).count();
+        let (changed, missing) = (count(Verified::Changed), count(Verified::Missing));
+        let unchecked = match count(Verified::NoChecksum) {
+            0 => String::new(),
+            n => format!(", {n} unchecked"),
+        };
+        writeln!(out, "{}: {} ok, {changed} changed, {missing} missing{unchecked}", dir.display(), count(Verified::Ok))?;
+        if changed + missing > 0 {
+            code = ExitCode::FAILURE;
+        }
+    }
+    Ok(code)
+}
@@ -0,0 +1,189 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use glob::{MatchOptions, Pattern};
+
+use crate::sbf::{MUSIC_FILES, SBF};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Globs adding files to or removing them from what `discover` picks. They are matched
+/// case-insensitively against the path relative to the game dir (with `/` separators) and against
+/// the bare file name.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    fn matches(patterns: &[Pattern], relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        patterns.iter().any(|p| p.matches_with(relative, MATCH_OPTIONS) || p.matches_with(name, MATCH_OPTIONS))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Music,
+    // matched an include pattern, used whatever the content looks like
+    Included,
+    Excluded,
+    // an SBF without music (or no SBF at all), with the reason
+    Other(String),
+    // same file name as an earlier file, which is the one used
+    Duplicate(String),
+}
+
+impl Status {
+    pub fn selected(&self) -> bool {
+        matches!(self, Status::Music | Status::Included)
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Music => write!(f, "music"),
+            Status::Included => write!(f, "included"),
+            Status::Excluded => write!(f, "excluded"),
+            Status::Other(reason) => write!(f, "not music: {reason}"),
+            Status::Duplicate(of) => write!(f, "skipped, same name as {of}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FoundFile {
+    pub path: PathBuf,
+    // relative to the game dir, `/` separated
+    pub relative: String,
+    pub status: Status,
+}
+
+impl FoundFile {
+    pub fn name(&self) -> &str {
+        self.relative.rsplit('/').next().unwrap_or(&self.relative)
+    }
+}
+
+/// Everything `discover` looked at, in the order the music files are used: the known files in
+/// `MUSIC_FILES` order (bare track names resolve to the first file containing them), then the rest
+/// by path.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    pub game_dir: PathBuf,
+    pub files: Vec<FoundFile>,
+}
+
+impl Discovery {
+    pub fn selected(&self) -> impl Iterator<Item = &FoundFile> {
+        self.files.iter().filter(|f| f.status.selected())
+    }
+
+    /// The files to use, an error if there are none.
+    pub fn music_files(&self) -> anyhow::Result<Vec<&FoundFile>> {
+        let files = self.selected().collect::<Vec<_>>();
+        if files.is_empty() {
+            return Err(anyhow!("no music SBFs found in {:?} (add files with --include)", self.game_dir));
+        }
+        Ok(files)
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<SBF>> {
+        self.music_files()?.into_iter().map(|f| SBF::from_file(&f.path)).collect()
+    }
+}
+
+// Music SBFs hold 8-bit stereo blocks whose headers have the `two_fifty` marker.
+fn classify(path: &Path) -> Status {
+    let sbf = match SBF::from_file(path) {
+        Ok(sbf) => sbf,
+        Err(e) => return Status::Other(e.root_cause().to_string()),
+    };
+    if sbf.chunks.is_empty() {
+        return Status::Other("empty index".to_owned());
+    }
+    for e in &sbf.chunks {
+        match sbf.segment_blocks(e).first() {
+            Some(block) if block.two_fifty == 250 && block.size as usize <= block.content.len() => {}
+            Some(_) => return Status::Other(format!("{}{} doesn't hold music blocks", e.ident, e.suffix)),
+            None => return Status::Other(format!("{}{} is empty", e.ident, e.suffix)),
+        }
+    }
+    Status::Music
+}
+
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .context(format!("couldn't read {dir:?}"))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), out)?;
+        } else {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Finds the SBF files below `game_dir`: `*.sbf` in any case plus whatever `filter` includes,
+/// minus what it excludes. A pattern of `filter` matching no file is an error.
+pub fn discover(game_dir: &Path, filter: &FileFilter) -> anyhow::Result<Discovery> {
+    let mut paths = vec![];
+    walk(game_dir, &mut paths)?;
+    let mut files = paths
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(game_dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            let is_sbf = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("sbf"));
+            let included = FileFilter::matches(&filter.include, &relative);
+            if !is_sbf && !included {
+                return None;
+            }
+            let status = if FileFilter::matches(&filter.exclude, &relative) {
+                Status::Excluded
+            } else if included {
+                Status::Included
+            } else {
+                classify(&path)
+            };
+            Some(FoundFile { path, relative, status })
+        })
+        .collect::<Vec<_>>();
+    // a glob meant for tracks (`--exclude m3`) would otherwise be ignored without a word
+    for pattern in filter.include.iter().chain(&filter.exclude) {
+        if !files.iter().any(|f| FileFilter::matches(std::slice::from_ref(pattern), &f.relative)) {
+            return Err(anyhow!(
+                "{:?} matches no file in {game_dir:?} (tracks are selected with --track and --exclude-track)",
+                pattern.as_str()
+            ));
+        }
+    }
+    let rank = |f: &FoundFile| {
+        MUSIC_FILES.iter().position(|m| m.eq_ignore_ascii_case(f.name())).unwrap_or(MUSIC_FILES.len())
+    };
+    // shallower paths first, so a copy in a subdirectory doesn't shadow the original
+    files.sort_by_cached_key(|f| (rank(f), f.relative.matches('/').count(), f.relative.to_lowercase()));
+    // tracks are qualified by file name, which has to be unique
+    for idx in 0..files.len() {
+        if !files[idx].status.selected() {
+            continue;
+        }
+        let earlier = files[..idx].iter().find(|f| f.status.selected() && f.name().eq_ignore_ascii_case(files[idx].name()));
+        if let Some(earlier) = earlier {
+            files[idx].status = Status::Duplicate(earlier.relative.clone());
+        }
+    }
+    Ok(Discovery {
+        game_dir: game_dir.to_owned(),
+        files,
+    })
+}
@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+
+use crate::flac::{read_streaminfo, FlacEncoder};
+use crate::enhance::DecodeMode;
+use crate::markers::{adtl_chunk, cue_chunk, Marker, Sidecars};
+use crate::metadata::TrackMetadata;
+use crate::mix::Joined;
+use crate::output::OutputFile;
+use crate::resample::Resample;
+use crate::segments::{segment_files, SegmentFiles};
+use crate::wav::{self, WavFormat, WavWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Wav,
+    Flac,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "wav" => Ok(OutputFormat::Wav),
+            "flac" => Ok(OutputFormat::Flac),
+            _ => Err(anyhow!("unknown output format: {s:?} (expected wav or flac)")),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Flac => "flac",
+        }
+    }
+
+    /// The audio properties of a file in this format, from its header.
+    pub fn read_info(&self, data: &[u8]) -> anyhow::Result<AudioInfo> {
+        match self {
+            OutputFormat::Wav => {
+                let (format, frames) = wav::read_header(data)?;
+                Ok(AudioInfo {
+                    channels: format.channels,
+                    sample_rate: format.sample_rate,
+                    bits: format.sample_format.bits(),
+                    frames,
+                })
+            }
+            OutputFormat::Flac => read_streaminfo(data),
+        }
+    }
+
+    pub fn create(&self, path: &Path, info: &TrackInfo) -> anyhow::Result<Box<dyn Encoder>> {
+        let file = File::create(path).context(format!("couldn't create {path:?}"))?;
+        Ok(match self {
+            OutputFormat::Wav => Box::new(WavEncoder::new(file, info)?),
+            OutputFormat::Flac => Box::new(FlacFileEncoder::new(file, info)?),
+        })
+    }
+}
+
+/// What the header of an exported file says about its audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits: u16,
+    pub frames: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    #[default]
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl SampleFormat {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "16" => Ok(SampleFormat::Int16),
+            "24" => Ok(SampleFormat::Int24),
+            "float" => Ok(SampleFormat::Float32),
+            _ => Err(anyhow!("unknown sample format: {s:?} (expected 16, 24 or float)")),
+        }
+    }
+
+    pub fn bits(&self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    // float in [-1, 1] to a signed integer of `bits`
+    pub fn quantize(&self, s: f32) -> i32 {
+        let full_scale = (1i32 << (self.bits() - 1)) as f32;
+        (s * full_scale).round().clamp(-full_scale, full_scale - 1.0) as i32
+    }
+}
+
+/// How the processors write tracks.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    pub format: OutputFormat,
+    // append the track title to file names
+    pub titled_names: bool,
+    pub sidecars: Sidecars,
+    pub segment_files: SegmentFiles,
+    pub resample: Option<Resample>,
+    pub decoder: DecodeMode,
+    pub sample_format: SampleFormat,
+}
+
+impl ExportOptions {
+    /// An encoder taking samples at the rate in `info`; with resampling `info` is updated to
+    /// describe the converted track.
+    pub fn create(&self, path: &Path, info: &mut TrackInfo) -> anyhow::Result<Box<dyn Encoder>> {
+        match self.resample {
+            Some(resample) if resample.rate != info.sample_rate => {
+                let (channels, from) = (info.channels, info.sample_rate);
+                resample.convert_info(info);
+                Ok(resample.wrap(channels, from, self.format.create(path, info)?))
+            }
+            _ => self.format.create(path, info),
+        }
+    }
+
+    /// The files written for the track `name` (`ident` in `source`), relative to the format
+    /// directory: the track as `<file_name>.<ext>` with its sidecars and the segments in
+    /// `<name>/`, as enabled. Their build key is left for the caller.
+    pub fn output_files(&self, name: &str, file_name: &str, source: &str, ident: &str, suffixes: &[String]) -> Vec<OutputFile> {
+        let segments = suffixes.iter().map(|suffix| format!("{ident}{suffix}")).collect::<Vec<_>>();
+        let file = |path: PathBuf, segments: Vec<String>| OutputFile {
+            path,
+            source: source.to_owned(),
+            segments,
+            key: String::new(),
+        };
+        let mut files = vec![];
+        if self.segment_files.segments() {
+            let names = segment_files(suffixes.iter().map(|s| s.as_str()), self.format);
+            // one file per segment, then the segment manifest covering all of them
+            for (idx, name_in_dir) in names.into_iter().enumerate() {
+                let segments = segments.get(idx).map_or(segments.clone(), |s| vec![s.clone()]);
+                files.push(file(Path::new(name).join(name_in_dir), segments));
+            }
+        }
+        if self.segment_files.tracks() {
+            let path = PathBuf::from(format!("{file_name}.{}", self.format.extension()));
+            files.extend(self.sidecars.paths(&path).into_iter().map(|p| file(p, segments.clone())));
+            files.push(file(path, segments));
+        }
+        files
+    }
+
+    /// Encodes a rendered track to `path` and writes its sidecars; the length and markers of
+    /// `info` are taken from `joined` and the segment names in `info.metadata`.
+    pub fn write_joined<S: EncodeSample>(&self, path: &Path, mut info: TrackInfo, joined: &Joined<S>) -> anyhow::Result<()> {
+        info.frames = joined.frames() as u64;
+        info.markers = Marker::from_lengths(info.metadata.segments.clone(), &joined.offsets, &joined.lengths);
+        let mut encoder = self.create(path, &mut info)?;
+        self.sidecars.write(path, &info)?;
+        S::write(encoder.as_mut(), &joined.samples)?;
+        encoder.finish()
+    }
+}
+
+/// What an encoder needs to know about a track before the first sample.
+pub struct TrackInfo {
+    pub metadata: TrackMetadata,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub frames: u64,
+    pub sample_format: SampleFormat,
+    // segment starts, written as cue points by formats that support them
+    pub markers: Vec<Marker>,
+}
+
+pub trait Encoder: Send {
+    /// Takes interleaved 16-bit samples.
+    fn write_samples(&mut self, samples: &[i16]) -> anyhow::Result<()>;
+    /// Takes interleaved samples in [-1, 1].
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()>;
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Sample types encoders take directly.
+pub trait EncodeSample: Copy {
+    fn write(encoder: &mut dyn Encoder, samples: &[Self]) -> anyhow::Result<()>;
+}
+
+impl EncodeSample for i16 {
+    fn write(encoder: &mut dyn Encoder, samples: &[Self]) -> anyhow::Result<()> {
+        encoder.write_samples(samples)
+    }
+}
+
+impl EncodeSample for f32 {
+    fn write(encoder: &mut dyn Encoder, samples: &[Self]) -> anyhow::Result<()> {
+        encoder.write_float(samples)
+    }
+}
+
+/// Streams to a `WavWriter` and patches the header at the end, so `info.frames` is only a hint.
+pub struct WavEncoder {
+    writer: WavWriter<File>,
+}
+
+impl WavEncoder {
+    pub fn new(file: File, info: &TrackInfo) -> anyhow::Result<Self> {
+        let format = WavFormat {
+            channels: info.channels,
+            sample_rate: info.sample_rate,
+            sample_format: info.sample_format,
+        };
+        Ok(Self {
+            writer: WavWriter::streaming(file, format, wav_chunks(info))?,
+        })
+    }
+}
+
+/// Metadata and marker chunks of a WAV file.
+pub fn wav_chunks(info: &TrackInfo) -> Vec<u8> {
+    let mut chunks = info.metadata.riff_info_chunk();
+    chunks.extend(info.metadata.id3_chunk());
+    if !info.markers.is_empty() {
+        chunks.extend(cue_chunk(&info.markers));
+        chunks.extend(adtl_chunk(&info.markers));
+    }
+    chunks
+}
+
+impl Encoder for WavEncoder {
+    fn write_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.writer.write_i16(samples)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.writer.write_f32(samples)
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+pub struct FlacFileEncoder {
+    encoder: FlacEncoder<BufWriter<File>>,
+    format: SampleFormat,
+    buffer: Vec<i32>,
+}
+
+impl FlacFileEncoder {
+    pub fn new(file: File, info: &TrackInfo) -> anyhow::Result<Self> {
+        if info.sample_format == SampleFormat::Float32 {
+            return Err(anyhow!("FLAC can't store float samples, use 16 or 24 bits"));
+        }
+        let comments = info.metadata.vorbis_comments();
+        let bits = info.sample_format.bits() as u32;
+        Ok(Self {
+            encoder: FlacEncoder::new(BufWriter::new(file), info.channels, bits, info.sample_rate, &comments)?,
+            format: info.sample_format,
+            buffer: vec![],
+        })
+    }
+}
+
+impl Encoder for FlacFileEncoder {
+    fn write_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.buffer.clear();
+        self.buffer.extend(samples.iter().map(|&s| (s as i32) << (self.format.bits() - 16)));
+        self.encoder.write_samples(&self.buffer)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.buffer.clear();
+        self.buffer.extend(samples.iter().map(|&s| self.format.quantize(s)));
+        self.encoder.write_samples(&self.buffer)
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.encoder.finish()?;
+        Ok(())
+    }
+}
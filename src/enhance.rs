@@ -0,0 +1,108 @@
+use anyhow::anyhow;
+
+use crate::sbf::{SBFChunkData, CHANNELS, SAMPLE_RATE};
+
+/// Settings of the enhanced decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Enhance {
+    // length of the gain ramp centered on every `scale1` change, 0 keeps the hard steps
+    pub gain_ramp_ms: f32,
+    // downward expander threshold in quantization steps of the current block, `None` disables it
+    pub noise_reduction: Option<f32>,
+}
+
+impl Default for Enhance {
+    fn default() -> Self {
+        Self {
+            gain_ramp_ms: 5.0,
+            noise_reduction: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DecodeMode {
+    // bit-exact `upscale_pcm`
+    #[default]
+    Classic,
+    Enhanced(Enhance),
+}
+
+impl DecodeMode {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "classic" => Ok(DecodeMode::Classic),
+            "enhanced" => Ok(DecodeMode::Enhanced(Enhance::default())),
+            _ => Err(anyhow!("unknown decoder: {s:?} (expected classic or enhanced)")),
+        }
+    }
+}
+
+// the weight of one 8-bit step in [-1, 1] output, the float equivalent of `upscale_pcm` minus its truncation
+fn log2_step(scale: u8) -> f32 {
+    -8.0 - scale as f32
+}
+
+/// Decodes blocks to interleaved float samples in [-1, 1]. Instead of switching the gain at the
+/// block boundary, the log gain is ramped linearly over `gain_ramp_ms` centered on every `scale1`
+/// change.
+pub fn decode_blocks(blocks: &[SBFChunkData], settings: &Enhance) -> Vec<f32> {
+    let ch = CHANNELS as usize;
+    let mut gains = Vec::with_capacity(blocks.iter().map(|b| b.size as usize / ch).sum());
+    let mut boundaries = vec![];
+    for block in blocks {
+        if !gains.is_empty() {
+            boundaries.push(gains.len());
+        }
+        gains.extend(std::iter::repeat_n(log2_step(block.scale1), block.size as usize / ch));
+    }
+    let ramp = (settings.gain_ramp_ms * SAMPLE_RATE as f32 / 1000.0) as usize;
+    let mut start_of = 0;
+    for (idx, &boundary) in boundaries.iter().enumerate() {
+        let end_of_next = boundaries.get(idx + 1).copied().unwrap_or(gains.len());
+        let (before, after) = (gains[boundary - 1], gains[boundary]);
+        // at most half of either block so neighbouring ramps never overlap
+        let half = (ramp / 2).min((boundary - start_of) / 2).min((end_of_next - boundary) / 2);
+        start_of = boundary;
+        if before == after || half == 0 {
+            continue;
+        }
+        for i in 0..2 * half {
+            let t = (i as f32 + 0.5) / (2 * half) as f32;
+            gains[boundary - half + i] = before + (after - before) * t;
+        }
+    }
+
+    let mut samples = Vec::with_capacity(gains.len() * ch);
+    for block in blocks {
+        for frame in block.content[0..block.size as usize].chunks_exact(ch) {
+            let gain = gains[samples.len() / ch].exp2();
+            samples.extend(frame.iter().map(|&b| (b as f32 - 128.0) * gain));
+        }
+    }
+    if let Some(threshold) = settings.noise_reduction {
+        expand(&mut samples, &gains, threshold);
+    }
+    samples
+}
+
+// Downward expander (1:2 below the threshold, at most -20 dB) per channel, against the 8-bit
+// quantization noise that's left exposed in quiet passages. `log2_steps` is the step size per frame.
+fn expand(samples: &mut [f32], log2_steps: &[f32], threshold: f32) {
+    let ch = CHANNELS as usize;
+    let coefficient = |ms: f32| (-1.0 / (ms * SAMPLE_RATE as f32 / 1000.0)).exp();
+    let (envelope_c, attack_c, release_c) = (coefficient(5.0), coefficient(2.0), coefficient(50.0));
+    for c in 0..ch {
+        let mut power = 0.0;
+        let mut gain = 1.0;
+        for (frame, &log2_step) in log2_steps.iter().enumerate() {
+            let s = samples[frame * ch + c];
+            power = envelope_c * power + (1.0 - envelope_c) * s * s;
+            let level = power.sqrt() / (threshold * log2_step.exp2());
+            let target = level.clamp(0.1, 1.0);
+            let coefficient = if target < gain { attack_c } else { release_c };
+            gain = coefficient * gain + (1.0 - coefficient) * target;
+            samples[frame * ch + c] = s * gain;
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_json::{json, Map, Value};
+
+use crate::hash::{to_hex, Md5};
+use crate::sbf::{SBFHeader, SBFIndexEntryBin, SBF};
+
+pub const DEFAULT_FINGERPRINTS_PATH: &str = "fingerprints.json";
+
+fn md5(data: &[u8]) -> String {
+    let mut md5 = Md5::default();
+    md5.update(data);
+    to_hex(&md5.finish())
+}
+
+/// What identifies the content of an SBF: its size, the MD5 of the header and index and the MD5 of
+/// every segment, keyed by full segment name (`m3a010a`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub index_md5: String,
+    pub segments: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    pub fn of(sbf: &SBF) -> Self {
+        let index_end = sbf.header.index_offset as usize + sbf.chunks.len() * size_of::<SBFIndexEntryBin>();
+        let mut index = Md5::default();
+        index.update(&sbf.content[0..size_of::<SBFHeader>()]);
+        index.update(&sbf.content[sbf.header.index_offset as usize..index_end]);
+        let segments = sbf
+            .chunks
+            .iter()
+            .map(|e| (format!("{}{}", e.ident, e.suffix), md5(sbf.segment_data(e))))
+            .collect();
+        Self {
+            size: sbf.content.len() as u64,
+            index_md5: to_hex(&index.finish()),
+            segments,
+        }
+    }
+}
+
+/// A file as shipped by one release of the game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub game: String,
+    pub version: String,
+    // album and year for the tags of tracks from this file, the defaults when unset
+    pub album: Option<String>,
+    pub year: Option<String>,
+    pub file: String,
+    pub fingerprint: Fingerprint,
+}
+
+impl Release {
+    // "Delta Force: Black Hawk Down 1.5.0.5"
+    pub fn name(&self) -> String {
+        format!("{} {}", self.game, self.version)
+    }
+
+    fn from_json(obj: &Map<String, Value>) -> anyhow::Result<Self> {
+        let string = |key: &str| -> anyhow::Result<Option<String>> {
+            obj.get(key)
+                .map(|v| v.as_str().map(|s| s.to_owned()).ok_or(anyhow!("{key} must be a string, got {v}")))
+                .transpose()
+        };
+        let required = |key: &str| string(key)?.ok_or(anyhow!("missing {key:?}"));
+        let size = obj
+            .get("size")
+            .ok_or(anyhow!("missing \"size\""))?
+            .as_u64()
+            .ok_or(anyhow!("size must be a non-negative integer"))?;
+        let segments = obj
+            .get("segments")
+            .ok_or(anyhow!("missing \"segments\""))?
+            .as_object()
+            .ok_or(anyhow!("segments must be an object"))?
+            .iter()
+            .map(|(name, md5)| Ok((name.to_owned(), md5.as_str().ok_or(anyhow!("segment {name:?} must be a string"))?.to_owned())))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            game: required("game")?,
+            version: required("version")?,
+            album: string("album")?,
+            year: string("year")?,
+            file: required("file")?,
+            fingerprint: Fingerprint {
+                size,
+                index_md5: required("index_md5")?,
+                segments,
+            },
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("game".into(), self.game.clone().into());
+        obj.insert("version".into(), self.version.clone().into());
+        if let Some(album) = &self.album {
+            obj.insert("album".into(), album.clone().into());
+        }
+        if let Some(year) = &self.year {
+            obj.insert("year".into(), year.clone().into());
+        }
+        obj.insert("file".into(), self.file.clone().into());
+        obj.insert("size".into(), self.fingerprint.size.into());
+        obj.insert("index_md5".into(), self.fingerprint.index_md5.clone().into());
+        obj.insert("segments".into(), json!(self.fingerprint.segments));
+        obj.into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identification<'a> {
+    Known(&'a Release),
+    // closest release by segment content; a file with no segment changes was only repacked
+    Modified {
+        release: &'a Release,
+        changed: Vec<String>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    Unknown,
+}
+
+impl Identification<'_> {
+    pub fn release(&self) -> Option<&Release> {
+        match self {
+            Identification::Known(release) | Identification::Modified { release, .. } => Some(release),
+            Identification::Unknown => None,
+        }
+    }
+
+    pub fn tags(&self) -> Option<ReleaseTags> {
+        let (release, name) = match self {
+            Identification::Known(release) => (release, release.name()),
+            Identification::Modified { release, .. } => (release, format!("{} (modified)", release.name())),
+            Identification::Unknown => return None,
+        };
+        Some(ReleaseTags {
+            name,
+            album: release.album.clone(),
+            year: release.year.clone(),
+        })
+    }
+}
+
+/// What the tags of exported tracks take from the release their SBF was identified as.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReleaseTags {
+    // the release, marked when the file doesn't match it exactly
+    pub name: String,
+    pub album: Option<String>,
+    pub year: Option<String>,
+}
+
+/// Known releases: the entries of `fingerprints.json` (a list of releases) recorded from files at
+/// hand with `dfbhd identify --add`. No releases are built in.
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintDatabase {
+    pub releases: Vec<Release>,
+}
+
+impl FingerprintDatabase {
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_str(content)?;
+        let list = value.as_array().ok_or(anyhow!("top level must be a list of releases"))?;
+        let releases = list
+            .iter()
+            .enumerate()
+            .map(|(idx, v)| {
+                let obj = v.as_object().ok_or(anyhow!("release {idx} must be an object"))?;
+                Release::from_json(obj).context(format!("release {idx}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { releases })
+    }
+
+    /// The entries of `path`, empty if it doesn't exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).context(format!("couldn't read {path:?}"))?;
+        Self::from_json(&content).context(format!("invalid fingerprint database {path:?}"))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&Value::Array(self.releases.iter().map(|r| r.to_json()).collect())).unwrap()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_json()).context(format!("couldn't write {path:?}"))
+    }
+
+    /// Which release the SBF `file` with `fingerprint` comes from: one with the same content, or
+    /// the one sharing the most segments (preferring the same file name).
+    pub fn identify(&self, file: &str, fingerprint: &Fingerprint) -> Identification<'_> {
+        if let Some(release) = self.releases.iter().find(|r| &r.fingerprint == fingerprint) {
+            return Identification::Known(release);
+        }
+        let matching = |r: &Release| {
+            let segments = fingerprint.segments.iter().filter(|(name, md5)| r.fingerprint.segments.get(*name) == Some(md5)).count();
+            (segments, r.file.eq_ignore_ascii_case(file))
+        };
+        let closest = self.releases.iter().map(|r| (matching(r), r)).filter(|((n, _), _)| *n > 0).max_by_key(|(m, _)| *m);
+        let Some((_, release)) = closest else {
+            return Identification::Unknown;
+        };
+        let theirs = &release.fingerprint.segments;
+        let ours = &fingerprint.segments;
+        Identification::Modified {
+            release,
+            changed: ours.iter().filter(|(name, md5)| theirs.get(*name).is_some_and(|m| m != *md5)).map(|(name, _)| name.clone()).collect(),
+            added: ours.keys().filter(|name| !theirs.contains_key(*name)).cloned().collect(),
+            removed: theirs.keys().filter(|name| !ours.contains_key(*name)).cloned().collect(),
+        }
+    }
+}
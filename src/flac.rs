@@ -0,0 +1,426 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use anyhow::anyhow;
+
+use crate::encoder::AudioInfo;
+use crate::hash::Md5;
+use crate::metadata::ENCODER;
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_RICE_PARAM: u32 = 14;
+const MAX_PARTITION_ORDER: u32 = 8;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::with_capacity(BLOCK_SIZE * 4), acc: 0, bits: 0 }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, zeros: u32) {
+        let mut zeros = zeros;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|w| {
+            let s = |back: usize| w[order - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+// Best rice parameter for a partition and the number of bits it takes.
+fn rice_cost(residual: &[i64]) -> (u32, u64) {
+    let sum = residual.iter().map(|&r| zigzag(r)).sum::<u64>();
+    (0..=MAX_RICE_PARAM)
+        .map(|k| (k, residual.len() as u64 * (k as u64 + 1) + (sum >> k)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+// channel data and the extra bits per sample it needs (the side channel is one bit wider)
+type Subframe<'a> = (&'a [i64], u32);
+
+struct Residual {
+    partition_order: u32,
+    params: Vec<u32>,
+    bits: u64,
+}
+
+fn plan_residual(residual: &[i64], block_size: usize, order: usize) -> Residual {
+    let mut best: Option<Residual> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        if !block_size.is_multiple_of(partitions) || block_size / partitions <= order {
+            break;
+        }
+        let partition_len = block_size / partitions;
+        let mut params = Vec::with_capacity(partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for p in 0..partitions {
+            let len = if p == 0 { partition_len - order } else { partition_len };
+            let (k, cost) = rice_cost(&residual[start..start + len]);
+            params.push(k);
+            bits += cost + 4;
+            start += len;
+        }
+        if best.as_ref().map(|b| bits < b.bits).unwrap_or(true) {
+            best = Some(Residual { partition_order, params, bits });
+        }
+    }
+    best.unwrap()
+}
+
+fn write_subframe(w: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        w.write(0, 1);
+        w.write(0b000000, 6);
+        w.write(0, 1);
+        w.write_signed(samples[0], bits_per_sample);
+        return;
+    }
+    let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+    let mut best: Option<(usize, Vec<i64>, Residual)> = None;
+    for order in 0..=4.min(samples.len() - 1) {
+        let residual = fixed_residual(samples, order);
+        let plan = plan_residual(&residual, samples.len(), order);
+        let bits = plan.bits + (order as u64) * bits_per_sample as u64;
+        if best.as_ref().map(|(o, _, b)| bits < b.bits + (*o as u64) * bits_per_sample as u64).unwrap_or(true) {
+            best = Some((order, residual, plan));
+        }
+    }
+    let (order, residual, plan) = best.unwrap();
+    if plan.bits + (order as u64) * bits_per_sample as u64 >= verbatim_bits {
+        w.write(0, 1);
+        w.write(0b000001, 6);
+        w.write(0, 1);
+        for &s in samples {
+            w.write_signed(s, bits_per_sample);
+        }
+        return;
+    }
+    w.write(0, 1);
+    w.write(0b001000 | order as u64, 6);
+    w.write(0, 1);
+    for &s in &samples[..order] {
+        w.write_signed(s, bits_per_sample);
+    }
+    w.write(0b00, 2);
+    w.write(plan.partition_order as u64, 4);
+    let partition_len = samples.len() >> plan.partition_order;
+    let mut start = 0;
+    for (p, &k) in plan.params.iter().enumerate() {
+        let len = if p == 0 { partition_len - order } else { partition_len };
+        w.write(k as u64, 4);
+        for &r in &residual[start..start + len] {
+            let u = zigzag(r);
+            w.write_unary((u >> k) as u32);
+            w.write(u, k);
+        }
+        start += len;
+    }
+}
+
+fn write_utf8_number(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.write(n, 8);
+        return;
+    }
+    let extra = match n {
+        0..=0x7ff => 1,
+        0x800..=0xffff => 2,
+        0x10000..=0x1fffff => 3,
+        0x200000..=0x3ffffff => 4,
+        _ => 5,
+    };
+    let lead_bits = 6 - extra;
+    let lead_marker = (0xffu64 << (7 - extra)) & 0xff;
+    w.write(lead_marker | (n >> (6 * extra)), 8);
+    debug_assert!(n >> (6 * extra) < 1 << lead_bits);
+    for i in (0..extra).rev() {
+        w.write(0x80 | ((n >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+/// The channels, sample rate, bit depth and length from the STREAMINFO of a FLAC file.
+pub fn read_streaminfo(data: &[u8]) -> anyhow::Result<AudioInfo> {
+    if !data.starts_with(b"fLaC") {
+        return Err(anyhow!("not a FLAC file"));
+    }
+    // the first metadata block is always STREAMINFO (type 0, 34 bytes)
+    let info = data.get(8..8 + 34).filter(|_| data[4] & 0x7f == 0).ok_or(anyhow!("missing STREAMINFO"))?;
+    let bits = u64::from_be_bytes(info[10..18].try_into().unwrap());
+    Ok(AudioInfo {
+        sample_rate: (bits >> 44) as u32,
+        channels: ((bits >> 41) & 0x7) as u16 + 1,
+        bits: ((bits >> 36) & 0x1f) as u16 + 1,
+        frames: bits & 0xf_ffff_ffff,
+    })
+}
+
+pub struct FlacEncoder<W: Write + Seek> {
+    writer: W,
+    channels: usize,
+    bits_per_sample: u32,
+    sample_rate: u32,
+    pending: Vec<i32>,
+    frame_number: u64,
+    frames_written: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    md5: Md5,
+    streaminfo_offset: u64,
+}
+
+impl<W: Write + Seek> FlacEncoder<W> {
+    /// Writes the stream marker and metadata; `comments` end up in a VORBIS_COMMENT block.
+    pub fn new(
+        mut writer: W,
+        channels: u16,
+        bits_per_sample: u32,
+        sample_rate: u32,
+        comments: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        writer.write_all(b"fLaC")?;
+        let streaminfo_offset = writer.stream_position()?;
+        let mut encoder = Self {
+            writer,
+            channels: channels as usize,
+            bits_per_sample,
+            sample_rate,
+            pending: Vec::with_capacity(BLOCK_SIZE * channels as usize),
+            frame_number: 0,
+            frames_written: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            md5: Md5::default(),
+            streaminfo_offset,
+        };
+        encoder.write_streaminfo(&[0; 16])?;
+
+        let vendor = ENCODER;
+        let mut block = vec![];
+        block.extend((vendor.len() as u32).to_le_bytes());
+        block.extend(vendor.as_bytes());
+        block.extend((comments.len() as u32).to_le_bytes());
+        for (key, value) in comments {
+            let comment = format!("{key}={value}");
+            block.extend((comment.len() as u32).to_le_bytes());
+            block.extend(comment.as_bytes());
+        }
+        // last metadata block, type 4 (VORBIS_COMMENT)
+        encoder.writer.write_all(&[0x80 | 4])?;
+        encoder.writer.write_all(&(block.len() as u32).to_be_bytes()[1..])?;
+        encoder.writer.write_all(&block)?;
+        Ok(encoder)
+    }
+
+    fn write_streaminfo(&mut self, md5: &[u8; 16]) -> anyhow::Result<()> {
+        let mut w = BitWriter::new();
+        w.write(0, 1);
+        w.write(0, 7);
+        w.write(34, 24);
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(BLOCK_SIZE as u64, 16);
+        let min_frame_size = if self.min_frame_size == u32::MAX { 0 } else { self.min_frame_size };
+        w.write(min_frame_size as u64, 24);
+        w.write(self.max_frame_size as u64, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits_per_sample as u64 - 1, 5);
+        w.write(self.frames_written >> 32, 4);
+        w.write(self.frames_written & 0xffff_ffff, 32);
+        self.writer.write_all(&w.bytes)?;
+        self.writer.write_all(md5)?;
+        Ok(())
+    }
+
+    /// Takes interleaved samples.
+    pub fn write_samples(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        let bytes_per_sample = self.bits_per_sample.div_ceil(8) as usize;
+        for &s in samples {
+            self.md5.update(&s.to_le_bytes()[..bytes_per_sample]);
+        }
+        let block_samples = BLOCK_SIZE * self.channels;
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let take = (block_samples - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() == block_samples {
+                self.flush_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_frame(&mut self) -> anyhow::Result<()> {
+        let ch = self.channels;
+        let block_size = self.pending.len() / ch;
+        if block_size == 0 {
+            return Ok(());
+        }
+        let channel = |c: usize| self.pending.iter().skip(c).step_by(ch).map(|&s| s as i64).collect::<Vec<_>>();
+        let channels = (0..ch).map(channel).collect::<Vec<_>>();
+        let side = if ch == 2 {
+            channels[0].iter().zip(&channels[1]).map(|(l, r)| l - r).collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+
+        let mut w = BitWriter::new();
+        w.write(0b11111111111110, 14);
+        w.write(0, 1);
+        w.write(0, 1);
+        let block_size_code = if block_size == BLOCK_SIZE { 0b1100 } else { 0b0111 };
+        w.write(block_size_code, 4);
+        let sample_rate_code = match self.sample_rate {
+            22050 => 0b0100,
+            44100 => 0b1001,
+            48000 => 0b1010,
+            96000 => 0b1011,
+            _ => 0b0000,
+        };
+        w.write(sample_rate_code, 4);
+
+        // for stereo pick the cheapest of independent, left/side, right/side and mid/side
+        let mid = if ch == 2 {
+            channels[0].iter().zip(&channels[1]).map(|(l, r)| (l + r) >> 1).collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        let candidates: Vec<(u64, Vec<Subframe>)> = if ch == 2 {
+            vec![
+                (0b0001, vec![(&channels[0], 0), (&channels[1], 0)]),
+                (0b1000, vec![(&channels[0], 0), (&side, 1)]),
+                (0b1001, vec![(&side, 1), (&channels[1], 0)]),
+                (0b1010, vec![(&mid, 0), (&side, 1)]),
+            ]
+        } else {
+            vec![(ch as u64 - 1, channels.iter().map(|c| (c.as_slice(), 0)).collect())]
+        };
+        let (assignment, subframes) = candidates
+            .into_iter()
+            .map(|(assignment, subframes)| {
+                let mut sw = BitWriter::new();
+                for (subframe, extra_bits) in subframes {
+                    write_subframe(&mut sw, subframe, self.bits_per_sample + extra_bits);
+                }
+                sw.align();
+                (assignment, sw)
+            })
+            .min_by_key(|(_, sw)| sw.bytes.len())
+            .unwrap();
+
+        w.write(assignment, 4);
+        let sample_size_code = match self.bits_per_sample {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            _ => 0b000,
+        };
+        w.write(sample_size_code, 3);
+        w.write(0, 1);
+        write_utf8_number(&mut w, self.frame_number);
+        if block_size_code == 0b0111 {
+            w.write(block_size as u64 - 1, 16);
+        }
+        let crc = crc8(&w.bytes);
+        w.write(crc as u64, 8);
+
+        let mut frame = w.bytes;
+        frame.extend_from_slice(&subframes.bytes);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        self.writer.write_all(&frame)?;
+
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        self.frame_number += 1;
+        self.frames_written += block_size as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Encodes the last partial block and rewrites STREAMINFO with the sample count, frame sizes and MD5.
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.flush_frame()?;
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.streaminfo_offset))?;
+        let md5 = std::mem::take(&mut self.md5).finish();
+        self.write_streaminfo(&md5)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
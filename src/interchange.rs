@@ -0,0 +1,156 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+
+use crate::mix::{frames_to_duration, Joined};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rust,
+    Json,
+    Csv,
+    Audacity,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Rust, Format::Json, Format::Csv, Format::Audacity];
+
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "rust" => Ok(Format::Rust),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "audacity" => Ok(Format::Audacity),
+            _ => Err(anyhow!("unknown arrangement format: {s:?} (expected rust, json, csv or audacity)")),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("rs") => Ok(Format::Rust),
+            Some("json") => Ok(Format::Json),
+            Some("csv") => Ok(Format::Csv),
+            Some("txt") => Ok(Format::Audacity),
+            _ => Err(anyhow!("can't tell the arrangement format of {path:?}, pass it explicitly")),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Rust => "rs",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Audacity => "txt",
+        }
+    }
+
+    pub fn needs_timings(&self) -> bool {
+        matches!(self, Format::Csv | Format::Audacity)
+    }
+}
+
+pub struct SegmentTiming {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+pub fn timings<S>(joined: &Joined<S>) -> Vec<SegmentTiming> {
+    joined
+        .offsets
+        .iter()
+        .zip(&joined.lengths)
+        .map(|(&offset, &length)| SegmentTiming {
+            start: frames_to_duration(offset),
+            end: frames_to_duration(offset + length),
+        })
+        .collect()
+}
+
+/// Writes the arrangement of `ident` as full segment names (`ident` + suffix). CSV and Audacity
+/// labels carry segment start/end times and need `timings`.
+pub fn export(format: Format, ident: &str, order: &[String], timings: Option<&[SegmentTiming]>) -> anyhow::Result<String> {
+    let names = order.iter().map(|suffix| format!("{ident}{suffix}")).collect::<Vec<_>>();
+    let timings = || timings.ok_or(anyhow!("{format:?} export needs segment timings"));
+    let mut out = String::new();
+    match format {
+        Format::Rust => {
+            out += &format!("let {ident}: [&str; {}] = {};\n", names.len(), serde_json::to_string(&names)?);
+        }
+        Format::Json => {
+            out += &serde_json::to_string_pretty(&names)?;
+            out += "\n";
+        }
+        Format::Csv => {
+            out += "index,segment,suffix,start,end,duration\n";
+            for (idx, ((name, suffix), t)) in names.iter().zip(order).zip(timings()?).enumerate() {
+                out += &format!(
+                    "{idx},{name},{suffix},{:.6},{:.6},{:.6}\n",
+                    t.start.as_secs_f64(),
+                    t.end.as_secs_f64(),
+                    (t.end - t.start).as_secs_f64()
+                );
+            }
+        }
+        Format::Audacity => {
+            for (name, t) in names.iter().zip(timings()?) {
+                out += &format!("{:.6}\t{:.6}\t{name}\n", t.start.as_secs_f64(), t.end.as_secs_f64());
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Reads back a segment order in any of the export formats. Names may be full (`m3a010a`) or bare
+/// suffixes; CSV and Audacity rows are ordered by start time.
+pub fn import(format: Format, ident: &str, content: &str) -> anyhow::Result<Vec<String>> {
+    let names = match format {
+        Format::Rust => {
+            // skip the `let NAME: [&str; N] =` part if there is one
+            let start = content.find('=').map(|eq| eq + 1).unwrap_or(0);
+            let start = start + content[start..].find('[').ok_or(anyhow!("no array found"))?;
+            let end = content.rfind(']').ok_or(anyhow!("unterminated array"))?;
+            serde_json::from_str::<Vec<String>>(&content[start..=end]).context("array must contain string literals")?
+        }
+        Format::Json => serde_json::from_str::<Vec<String>>(content)?,
+        Format::Csv => {
+            let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+            let header = lines.next().ok_or(anyhow!("empty CSV"))?.split(',').map(|h| h.trim()).collect::<Vec<_>>();
+            let column = |name: &str| header.iter().position(|h| *h == name);
+            let name_col = column("segment").or(column("suffix")).ok_or(anyhow!("CSV needs a segment or suffix column"))?;
+            let start_col = column("start");
+            let mut rows = lines
+                .map(|line| {
+                    let fields = line.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+                    let name = fields.get(name_col).ok_or(anyhow!("short CSV row: {line:?}"))?.to_string();
+                    let start = match start_col {
+                        Some(c) => fields.get(c).ok_or(anyhow!("short CSV row: {line:?}"))?.parse::<f64>()?,
+                        None => 0.0,
+                    };
+                    Ok((start, name))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+            rows.into_iter().map(|(_, name)| name).collect()
+        }
+        Format::Audacity => {
+            let mut labels = content
+                .lines()
+                .filter(|l| !l.trim().is_empty() && !l.starts_with('\\'))
+                .map(|line| {
+                    let mut fields = line.splitn(3, '\t');
+                    let start = fields.next().unwrap().trim().parse::<f64>().context(format!("bad label line: {line:?}"))?;
+                    let _end = fields.next();
+                    let name = fields.next().ok_or(anyhow!("label without text: {line:?}"))?.trim().to_owned();
+                    Ok((start, name))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            labels.sort_by(|a, b| a.0.total_cmp(&b.0));
+            labels.into_iter().map(|(_, name)| name).collect()
+        }
+    };
+    Ok(names
+        .into_iter()
+        .map(|name| name.strip_prefix(ident).map(|s| s.to_owned()).unwrap_or(name))
+        .collect())
+}
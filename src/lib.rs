@@ -1,8 +1,28 @@
+pub mod arrangement;
 pub mod cmd;
+pub mod discover;
+pub mod encoder;
+pub mod enhance;
+pub mod fingerprint;
+pub mod flac;
+pub mod hash;
+pub mod interchange;
+pub mod markers;
+pub mod merge;
+pub mod metadata;
+pub mod mix;
+pub mod output;
+pub mod resample;
 pub mod sbf;
+pub mod segments;
+pub mod select;
+pub mod titles;
+pub mod validate;
+pub mod wav;
 
 use std::mem::size_of;
 
+#[allow(clippy::mut_from_ref)]
 pub fn mm<T: ?Sized>(r : &T) -> &mut T {
     unsafe {
         #[allow(mutable_transmutes)]
@@ -11,15 +31,8 @@ pub fn mm<T: ?Sized>(r : &T) -> &mut T {
 }
 
 pub fn array_transmute<T, U>(data: &[T]) -> &[U] {
-    let bytes_count = data.len() * size_of::<T>();
+    let bytes_count = std::mem::size_of_val(data);
     assert_eq!(bytes_count % size_of::<U>(), 0);
-    log("ding");
-    let result = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const U, bytes_count / size_of::<U>()) };
-    log("dong");
-    result
-}
-
-pub fn log(s: impl Into<String>) {
-    let _ = std::fs::write("/dev/ttys011", format!("{}\n", s.into()));
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const U, bytes_count / size_of::<U>()) }
 }
 
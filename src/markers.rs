@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_json::json;
+
+use crate::encoder::TrackInfo;
+use crate::metadata::riff_chunk;
+
+/// Start of an original SBF segment in a rendered track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    // full segment name, `ident` + suffix
+    pub name: String,
+    pub start: u64,
+    pub frames: u64,
+}
+
+impl Marker {
+    pub fn from_lengths(names: Vec<String>, offsets: &[usize], lengths: &[usize]) -> Vec<Marker> {
+        names
+            .into_iter()
+            .zip(offsets.iter().zip(lengths))
+            .map(|(name, (&start, &frames))| Marker { name, start: start as u64, frames: frames as u64 })
+            .collect()
+    }
+}
+
+/// A `cue ` chunk with a point at the start of every marker, ids counting from 1.
+pub fn cue_chunk(markers: &[Marker]) -> Vec<u8> {
+    let mut body = (markers.len() as u32).to_le_bytes().to_vec();
+    for (idx, marker) in markers.iter().enumerate() {
+        body.extend((idx as u32 + 1).to_le_bytes());
+        body.extend((marker.start as u32).to_le_bytes());
+        body.extend(b"data");
+        body.extend(0u32.to_le_bytes());
+        body.extend(0u32.to_le_bytes());
+        body.extend((marker.start as u32).to_le_bytes());
+    }
+    let mut chunk = vec![];
+    riff_chunk(&mut chunk, b"cue ", &body);
+    chunk
+}
+
+/// A `LIST` chunk of type `adtl` naming every cue point (`labl`) and giving it the length of its
+/// segment (`ltxt`), so editors show the segments as regions.
+pub fn adtl_chunk(markers: &[Marker]) -> Vec<u8> {
+    let mut body = b"adtl".to_vec();
+    for (idx, marker) in markers.iter().enumerate() {
+        let id = (idx as u32 + 1).to_le_bytes();
+        let mut label = id.to_vec();
+        label.extend(marker.name.as_bytes());
+        label.push(0);
+        riff_chunk(&mut body, b"labl", &label);
+
+        let mut region = id.to_vec();
+        region.extend((marker.frames as u32).to_le_bytes());
+        region.extend(b"rgn ");
+        // country, language, dialect, code page
+        region.extend([0u8; 8]);
+        riff_chunk(&mut body, b"ltxt", &region);
+    }
+    let mut chunk = vec![];
+    riff_chunk(&mut chunk, b"LIST", &body);
+    chunk
+}
+
+// mm:ss:ff with 75 frames per second
+fn cue_time(frame: u64, sample_rate: u32) -> String {
+    let cd_frames = frame * 75 / sample_rate as u64;
+    format!("{:02}:{:02}:{:02}", cd_frames / 75 / 60, cd_frames / 75 % 60, cd_frames % 75)
+}
+
+fn cue_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// A cue sheet for `audio_file` with one track per segment.
+pub fn cue_sheet(audio_file: &str, info: &TrackInfo) -> anyhow::Result<String> {
+    if info.markers.len() > 99 {
+        return Err(anyhow!("cue sheets can't hold more than 99 tracks, got {} segments", info.markers.len()));
+    }
+    let metadata = &info.metadata;
+    let mut out = format!("REM COMMENT {}\n", cue_quote(&metadata.encoder));
+    if let Some(artist) = &metadata.artist {
+        out += &format!("PERFORMER {}\n", cue_quote(artist));
+    }
+    out += &format!("TITLE {}\n", cue_quote(&metadata.title));
+    out += &format!("FILE {} WAVE\n", cue_quote(audio_file));
+    for (idx, marker) in info.markers.iter().enumerate() {
+        out += &format!("  TRACK {:02} AUDIO\n", idx + 1);
+        out += &format!("    TITLE {}\n", cue_quote(&marker.name));
+        out += &format!("    INDEX 01 {}\n", cue_time(marker.start, info.sample_rate));
+    }
+    Ok(out)
+}
+
+/// Segment start/end times of `audio_file` in frames and seconds.
+pub fn timings_json(audio_file: &str, info: &TrackInfo) -> anyhow::Result<String> {
+    let seconds = |frames: u64| frames as f64 / info.sample_rate as f64;
+    let segments = info
+        .markers
+        .iter()
+        .map(|m| {
+            json!({
+                "name": m.name,
+                "start_frame": m.start,
+                "frames": m.frames,
+                "start": seconds(m.start),
+                "end": seconds(m.start + m.frames),
+            })
+        })
+        .collect::<Vec<_>>();
+    let value = json!({
+        "file": audio_file,
+        "source": info.metadata.source,
+        "sample_rate": info.sample_rate,
+        "frames": info.frames,
+        "segments": segments,
+    });
+    Ok(serde_json::to_string_pretty(&value)? + "\n")
+}
+
+/// Optional files written next to an exported track.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sidecars {
+    // `<name>.cue`
+    pub cue_sheet: bool,
+    // `<name>.timings.json`
+    pub timings: bool,
+}
+
+impl Sidecars {
+    fn cue_path(audio_path: &Path) -> PathBuf {
+        audio_path.with_file_name(format!("{}.cue", audio_path.file_stem().unwrap().to_string_lossy()))
+    }
+
+    fn timings_path(audio_path: &Path) -> PathBuf {
+        audio_path.with_file_name(format!("{}.timings.json", audio_path.file_stem().unwrap().to_string_lossy()))
+    }
+
+    /// The sidecars `write` creates for `audio_path`.
+    pub fn paths(&self, audio_path: &Path) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        if self.cue_sheet {
+            paths.push(Self::cue_path(audio_path));
+        }
+        if self.timings {
+            paths.push(Self::timings_path(audio_path));
+        }
+        paths
+    }
+
+    pub fn write(&self, audio_path: &Path, info: &TrackInfo) -> anyhow::Result<()> {
+        let audio_file = audio_path.file_name().unwrap().to_string_lossy();
+        if self.cue_sheet {
+            let path = Self::cue_path(audio_path);
+            std::fs::write(&path, cue_sheet(&audio_file, info)?).context(format!("couldn't write {path:?}"))?;
+        }
+        if self.timings {
+            let path = Self::timings_path(audio_path);
+            std::fs::write(&path, timings_json(&audio_file, info)?).context(format!("couldn't write {path:?}"))?;
+        }
+        Ok(())
+    }
+}
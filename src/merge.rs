@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+use crate::arrangement::{ArrangementConfig, JoinOverride, TrackArrangement};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderChange {
+    Moved { suffix: String, from: usize, to: usize },
+    Added { suffix: String, at: usize },
+    Removed { suffix: String, from: usize },
+}
+
+impl Display for OrderChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderChange::Moved { suffix, from, to } => write!(f, "{suffix} moved {from} -> {to}"),
+            OrderChange::Added { suffix, at } => write!(f, "+ {suffix} at {at}"),
+            OrderChange::Removed { suffix, from } => write!(f, "- {suffix} from {from}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackDiff {
+    Added { key: String, segments: usize },
+    Removed { key: String },
+    Changed { key: String, order: Vec<OrderChange>, joins_changed: bool },
+}
+
+impl Display for TrackDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrackDiff::Added { key, segments } => write!(f, "+ {key} ({segments} segments)"),
+            TrackDiff::Removed { key } => write!(f, "- {key}"),
+            TrackDiff::Changed { key, order, joins_changed } => {
+                write!(f, "{key}:")?;
+                for change in order {
+                    write!(f, "\n    {change}")?;
+                }
+                if *joins_changed {
+                    write!(f, "\n    join settings changed")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Segments outside the longest common subsequence of the two orders are the ones that moved.
+pub fn order_changes(old: &[String], new: &[String]) -> Vec<OrderChange> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut kept_old = vec![false; n];
+    let mut kept_new = vec![false; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            kept_old[i] = true;
+            kept_new[j] = true;
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    let mut changes = vec![];
+    let mut unmatched_old = (0..n).filter(|&i| !kept_old[i]).collect::<Vec<_>>();
+    for to in (0..m).filter(|&j| !kept_new[j]) {
+        match unmatched_old.iter().position(|&i| old[i] == new[to]) {
+            Some(pos) => {
+                let from = unmatched_old.remove(pos);
+                changes.push(OrderChange::Moved { suffix: new[to].to_owned(), from, to });
+            }
+            None => changes.push(OrderChange::Added { suffix: new[to].to_owned(), at: to }),
+        }
+    }
+    for from in unmatched_old {
+        changes.push(OrderChange::Removed { suffix: old[from].to_owned(), from });
+    }
+    changes
+}
+
+fn joins_equal(a: &TrackArrangement, b: &TrackArrangement) -> bool {
+    a.joins == b.joins && a.per_join == b.per_join
+}
+
+pub fn diff(old: &ArrangementConfig, new: &ArrangementConfig) -> Vec<TrackDiff> {
+    let keys = old.tracks.keys().chain(new.tracks.keys()).collect::<BTreeSet<_>>();
+    keys.into_iter()
+        .filter_map(|key| match (old.tracks.get(key), new.tracks.get(key)) {
+            (None, Some(b)) => Some(TrackDiff::Added { key: key.to_owned(), segments: b.order.len() }),
+            (Some(_), None) => Some(TrackDiff::Removed { key: key.to_owned() }),
+            (Some(a), Some(b)) if a != b => Some(TrackDiff::Changed {
+                key: key.to_owned(),
+                order: order_changes(&a.order, &b.order),
+                joins_changed: !joins_equal(a, b),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    // track key, or `$defaults`
+    pub key: String,
+    pub what: &'static str,
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} changed differently on both sides, kept ours", self.key, self.what)
+    }
+}
+
+pub struct Merged {
+    pub config: ArrangementConfig,
+    pub conflicts: Vec<Conflict>,
+}
+
+// Standard three-way pick: a side that didn't change defers to the other. `None` on conflict.
+fn pick<'a, T: PartialEq>(base: &'a T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+/// Merges two configs that were both derived from `base`, one track at a time. Segment order and
+/// join settings of a track merge independently, so one side reordering a track and the other
+/// changing its crossfade is not a conflict. Conflicting parts keep `ours`.
+pub fn merge(base: &ArrangementConfig, ours: &ArrangementConfig, theirs: &ArrangementConfig) -> Merged {
+    let mut conflicts = vec![];
+    let defaults = pick(&base.defaults, &ours.defaults, &theirs.defaults)
+        .unwrap_or_else(|| {
+            conflicts.push(Conflict { key: "$defaults".into(), what: "join settings" });
+            &ours.defaults
+        })
+        .clone();
+
+    let keys = base.tracks.keys()
+        .chain(ours.tracks.keys())
+        .chain(theirs.tracks.keys())
+        .collect::<BTreeSet<_>>();
+    let mut tracks = BTreeMap::new();
+    for key in keys {
+        let (b, o, t) = (base.tracks.get(key), ours.tracks.get(key), theirs.tracks.get(key));
+        let merged = match (b, o, t) {
+            (Some(b), Some(o), Some(t)) => {
+                let order = pick(&b.order, &o.order, &t.order).unwrap_or_else(|| {
+                    conflicts.push(Conflict { key: key.to_owned(), what: "segment order" });
+                    &o.order
+                });
+                let joins = pick(&b.joins, &o.joins, &t.joins).unwrap_or_else(|| {
+                    conflicts.push(Conflict { key: key.to_owned(), what: "join settings" });
+                    &o.joins
+                });
+                let per_join = merge_per_join(key, &b.per_join, &o.per_join, &t.per_join, &mut conflicts);
+                Some(TrackArrangement {
+                    order: order.clone(),
+                    joins: joins.clone(),
+                    per_join,
+                })
+            }
+            _ => match pick(&b, &o, &t) {
+                Some(picked) => picked.cloned(),
+                None => {
+                    // one side removed the track while the other edited it, or both added it differently
+                    conflicts.push(Conflict { key: key.to_owned(), what: "track" });
+                    o.cloned()
+                }
+            },
+        };
+        if let Some(merged) = merged {
+            tracks.insert(key.to_string(), merged);
+        }
+    }
+    Merged {
+        config: ArrangementConfig { defaults, tracks },
+        conflicts,
+    }
+}
+
+fn merge_per_join(
+    key: &str,
+    base: &BTreeMap<String, JoinOverride>,
+    ours: &BTreeMap<String, JoinOverride>,
+    theirs: &BTreeMap<String, JoinOverride>,
+    conflicts: &mut Vec<Conflict>,
+) -> BTreeMap<String, JoinOverride> {
+    let suffixes = base.keys().chain(ours.keys()).chain(theirs.keys()).collect::<BTreeSet<_>>();
+    let mut merged = BTreeMap::new();
+    for suffix in suffixes {
+        let (b, o, t) = (base.get(suffix), ours.get(suffix), theirs.get(suffix));
+        let picked = pick(&b, &o, &t).unwrap_or_else(|| {
+            conflicts.push(Conflict { key: format!("{key} join {suffix}"), what: "join settings" });
+            &o
+        });
+        if let Some(picked) = *picked {
+            merged.insert(suffix.to_string(), picked.clone());
+        }
+    }
+    merged
+}
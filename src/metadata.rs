@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+
+use crate::arrangement::qualified_key;
+use crate::fingerprint::ReleaseTags;
+use crate::titles::TitleDatabase;
+
+pub const DEFAULT_METADATA_PATH: &str = "track_metadata.json";
+pub const ENCODER: &str = concat!("dfbhd_mus ", env!("CARGO_PKG_VERSION"));
+const DEFAULTS_KEY: &str = "$defaults";
+
+pub fn default_album(source: &str) -> &'static str {
+    if source.eq_ignore_ascii_case("EXP1.sbf") {
+        "Delta Force: Black Hawk Down - Team Sabre"
+    } else {
+        "Delta Force: Black Hawk Down"
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackMetadata {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: String,
+    pub track_number: Option<u32>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub comment: Option<String>,
+    // SBF the track was decoded from
+    pub source: String,
+    // game release the SBF was identified as
+    pub release: Option<String>,
+    // full segment names in the order they were rendered
+    pub segments: Vec<String>,
+    pub encoder: String,
+}
+
+impl TrackMetadata {
+    pub fn vorbis_comments(&self) -> Vec<(String, String)> {
+        let mut comments = vec![("TITLE".to_owned(), self.title.to_owned()), ("ALBUM".to_owned(), self.album.to_owned())];
+        let optional = [
+            ("ARTIST", self.artist.clone()),
+            ("TRACKNUMBER", self.track_number.map(|n| n.to_string())),
+            ("GENRE", self.genre.clone()),
+            ("DATE", self.year.clone()),
+            ("COMMENT", self.comment.clone()),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                comments.push((key.to_owned(), value));
+            }
+        }
+        comments.push(("SOURCE".to_owned(), self.source.to_owned()));
+        if let Some(release) = &self.release {
+            comments.push(("RELEASE".to_owned(), release.to_owned()));
+        }
+        comments.push(("SEGMENTS".to_owned(), self.segments.join(" ")));
+        comments.push(("ENCODER".to_owned(), self.encoder.to_owned()));
+        comments
+    }
+
+    // RIFF INFO has no fields for the source and segment order, they go into the comment
+    fn info_comment(&self) -> String {
+        let mut comment = self.comment.clone().map(|c| format!("{c}\n")).unwrap_or_default();
+        comment += &format!("source: {}\n", self.source);
+        if let Some(release) = &self.release {
+            comment += &format!("release: {release}\n");
+        }
+        comment += &format!("segments: {}", self.segments.join(" "));
+        comment
+    }
+
+    /// A complete `LIST` chunk of type `INFO`.
+    pub fn riff_info_chunk(&self) -> Vec<u8> {
+        let mut fields = vec![("INAM", self.title.to_owned()), ("IPRD", self.album.to_owned())];
+        let optional = [
+            ("IART", self.artist.clone()),
+            ("ITRK", self.track_number.map(|n| n.to_string())),
+            ("IGNR", self.genre.clone()),
+            ("ICRD", self.year.clone()),
+        ];
+        fields.extend(optional.into_iter().filter_map(|(id, value)| value.map(|v| (id, v))));
+        fields.push(("ICMT", self.info_comment()));
+        fields.push(("ISFT", self.encoder.to_owned()));
+
+        let mut body = b"INFO".to_vec();
+        for (id, value) in fields {
+            let mut data = value.into_bytes();
+            data.push(0);
+            riff_chunk(&mut body, id.as_bytes(), &data);
+        }
+        let mut chunk = vec![];
+        riff_chunk(&mut chunk, b"LIST", &body);
+        chunk
+    }
+
+    /// A complete `id3 ` chunk holding an ID3v2.3 tag.
+    pub fn id3_chunk(&self) -> Vec<u8> {
+        let mut frames = vec![];
+        id3_text_frame(&mut frames, b"TIT2", &self.title);
+        id3_text_frame(&mut frames, b"TALB", &self.album);
+        if let Some(artist) = &self.artist {
+            id3_text_frame(&mut frames, b"TPE1", artist);
+        }
+        if let Some(n) = self.track_number {
+            id3_text_frame(&mut frames, b"TRCK", &n.to_string());
+        }
+        if let Some(genre) = &self.genre {
+            id3_text_frame(&mut frames, b"TCON", genre);
+        }
+        if let Some(year) = &self.year {
+            id3_text_frame(&mut frames, b"TYER", year);
+        }
+        if let Some(comment) = &self.comment {
+            let mut content = vec![id3_encoding(comment)];
+            content.extend(b"eng");
+            content.extend(id3_string(comment, "", true));
+            content.extend(id3_string(comment, comment, false));
+            id3_frame(&mut frames, b"COMM", &content);
+        }
+        id3_text_frame(&mut frames, b"TSSE", &self.encoder);
+        id3_user_frame(&mut frames, "SOURCE", &self.source);
+        if let Some(release) = &self.release {
+            id3_user_frame(&mut frames, "RELEASE", release);
+        }
+        id3_user_frame(&mut frames, "SEGMENTS", &self.segments.join(" "));
+
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() as u32;
+        tag.extend([(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f, (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]);
+        tag.extend(frames);
+        let mut chunk = vec![];
+        riff_chunk(&mut chunk, b"id3 ", &tag);
+        chunk
+    }
+}
+
+pub fn riff_chunk(out: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+    out.extend(id);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+// ISO-8859-1 when possible, UTF-16 with BOM otherwise
+fn id3_encoding(s: &str) -> u8 {
+    if s.chars().all(|c| (c as u32) < 0x100) {
+        0
+    } else {
+        1
+    }
+}
+
+// `encoding_of` decides the encoding so several strings of one frame share it
+fn id3_string(encoding_of: &str, s: &str, terminated: bool) -> Vec<u8> {
+    let mut out = vec![];
+    if id3_encoding(encoding_of) == 0 {
+        out.extend(s.chars().map(|c| c as u8));
+        if terminated {
+            out.push(0);
+        }
+    } else {
+        out.extend([0xff, 0xfe]);
+        out.extend(s.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        if terminated {
+            out.extend([0, 0]);
+        }
+    }
+    out
+}
+
+fn id3_frame(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_be_bytes());
+    out.extend([0, 0]);
+    out.extend(content);
+}
+
+fn id3_text_frame(out: &mut Vec<u8>, id: &[u8; 4], text: &str) {
+    let mut content = vec![id3_encoding(text)];
+    content.extend(id3_string(text, text, false));
+    id3_frame(out, id, &content);
+}
+
+fn id3_user_frame(out: &mut Vec<u8>, description: &str, value: &str) {
+    let both = format!("{description}{value}");
+    let mut content = vec![id3_encoding(&both)];
+    content.extend(id3_string(&both, description, true));
+    content.extend(id3_string(&both, value, false));
+    id3_frame(out, b"TXXX", &content);
+}
+
+// Fields a user can set in the metadata file, at `$defaults` or per track.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetadataOverride {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl MetadataOverride {
+    fn apply(&self, metadata: &mut TrackMetadata) {
+        if let Some(title) = &self.title {
+            metadata.title = title.to_owned();
+        }
+        if let Some(album) = &self.album {
+            metadata.album = album.to_owned();
+        }
+        if self.track_number.is_some() {
+            metadata.track_number = self.track_number;
+        }
+        for (field, value) in [
+            (&mut metadata.artist, &self.artist),
+            (&mut metadata.genre, &self.genre),
+            (&mut metadata.year, &self.year),
+            (&mut metadata.comment, &self.comment),
+        ] {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+    }
+
+    fn from_json(obj: &Map<String, Value>) -> anyhow::Result<Self> {
+        let string = |key: &str| -> anyhow::Result<Option<String>> {
+            obj.get(key)
+                .map(|v| match v {
+                    Value::String(s) => Ok(s.to_owned()),
+                    Value::Number(n) => Ok(n.to_string()),
+                    _ => Err(anyhow!("{key} must be a string, got {v}")),
+                })
+                .transpose()
+        };
+        let track_number = obj
+            .get("track")
+            .map(|v| {
+                v.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or(anyhow!("track must be a non-negative integer, got {v}"))
+            })
+            .transpose()?;
+        Ok(Self {
+            title: string("title")?,
+            artist: string("artist")?,
+            album: string("album")?,
+            track_number,
+            genre: string("genre")?,
+            year: string("year")?,
+            comment: string("comment")?,
+        })
+    }
+}
+
+/// Contents of `track_metadata.json`: tags keyed by track name (bare or qualified like
+/// `EXP1.sbf:m3`), with `$defaults` applying to every track. Titles and descriptions from `titles`
+/// and album and year of the release in `releases` (keyed by SBF name) are used where neither sets one.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataFile {
+    pub defaults: MetadataOverride,
+    pub tracks: BTreeMap<String, MetadataOverride>,
+    pub titles: TitleDatabase,
+    pub releases: BTreeMap<String, ReleaseTags>,
+}
+
+impl MetadataFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path).context(format!("couldn't read {path:?}"))?;
+        Self::from_json(&content).context(format!("invalid metadata file {path:?}"))
+    }
+
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_str(content)?;
+        let obj = value.as_object().ok_or(anyhow!("top level must be an object"))?;
+        let mut file = Self::default();
+        for (key, v) in obj {
+            let fields = v.as_object().ok_or(anyhow!("{key} must be an object"))?;
+            let fields = MetadataOverride::from_json(fields).context(key.to_owned())?;
+            if key == DEFAULTS_KEY {
+                file.defaults = fields;
+            } else {
+                file.tracks.insert(key.to_owned(), fields);
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn resolve(&self, source: &str, ident: &str, segments: Vec<String>) -> TrackMetadata {
+        let mut metadata = TrackMetadata {
+            title: ident.to_owned(),
+            album: default_album(source).to_owned(),
+            source: source.to_owned(),
+            segments,
+            encoder: ENCODER.to_owned(),
+            ..Default::default()
+        };
+        if let Some(title) = self.titles.get(source, ident) {
+            if let Some(t) = title.title {
+                metadata.title = t;
+            }
+            metadata.comment = title.description;
+        }
+        if let Some((_, release)) = self.releases.iter().find(|(file, _)| file.eq_ignore_ascii_case(source)) {
+            if let Some(album) = &release.album {
+                metadata.album = album.to_owned();
+            }
+            metadata.year = release.year.clone();
+            metadata.release = Some(release.name.to_owned());
+        }
+        self.defaults.apply(&mut metadata);
+        if let Some(o) = self.tracks.get(ident) {
+            o.apply(&mut metadata);
+        }
+        let qualified = self
+            .tracks
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&qualified_key(source, ident)));
+        if let Some((_, o)) = qualified {
+            o.apply(&mut metadata);
+        }
+        metadata
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use crate::sbf::{CHANNELS, SAMPLE_RATE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    Linear,
+    EqualPower,
+    SCurve,
+}
+
+impl Curve {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "linear" => Ok(Curve::Linear),
+            "equal-power" => Ok(Curve::EqualPower),
+            "s-curve" => Ok(Curve::SCurve),
+            _ => Err(anyhow::anyhow!("unknown crossfade curve: {s:?} (expected linear, equal-power or s-curve)")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Linear => "linear",
+            Curve::EqualPower => "equal-power",
+            Curve::SCurve => "s-curve",
+        }
+    }
+
+    // (outgoing gain, incoming gain) at position t in [0, 1] of the fade
+    pub fn gains(&self, t: f32) -> (f32, f32) {
+        match self {
+            Curve::Linear => (1.0 - t, t),
+            Curve::EqualPower => {
+                let a = t * std::f32::consts::FRAC_PI_2;
+                (a.cos(), a.sin())
+            }
+            Curve::SCurve => {
+                let s = t * t * (3.0 - 2.0 * t);
+                (1.0 - s, s)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JoinSettings {
+    pub crossfade_ms: u32,
+    pub curve: Curve,
+    // micro-fade applied on both sides of a join that has no crossfade, 0 disables it
+    pub declick_ms: u32,
+}
+
+impl Default for JoinSettings {
+    fn default() -> Self {
+        Self {
+            crossfade_ms: 0,
+            curve: Curve::EqualPower,
+            declick_ms: 0,
+        }
+    }
+}
+
+/// A sample type the mixer can fade: 16-bit from the classic decoder or float in [-1, 1] from
+/// the enhanced one.
+pub trait Sample: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(v: f32) -> Self;
+}
+
+impl Sample for i16 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(v: f32) -> Self {
+        v.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(v: f32) -> Self {
+        v
+    }
+}
+
+/// Interleaved samples of several segments joined together, with the frame at which each
+/// segment starts in `samples` and its length in frames (segments overlap by the crossfade).
+#[derive(Debug, Clone, Default)]
+pub struct Joined<S = i16> {
+    pub samples: Vec<S>,
+    pub offsets: Vec<usize>,
+    pub lengths: Vec<usize>,
+}
+
+impl<S> Joined<S> {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / CHANNELS as usize
+    }
+
+    pub fn offset_duration(&self, segment: usize) -> Duration {
+        frames_to_duration(self.offsets[segment])
+    }
+}
+
+pub fn ms_to_frames(ms: u32) -> usize {
+    (ms as u64 * SAMPLE_RATE as u64 / 1000) as usize
+}
+
+pub fn frames_to_duration(frames: usize) -> Duration {
+    Duration::from_secs_f64(frames as f64 / SAMPLE_RATE as f64)
+}
+
+pub fn duration_to_frames(duration: Duration) -> usize {
+    (duration.as_secs_f64() * SAMPLE_RATE as f64).round() as usize
+}
+
+/// Parses `90`, `90.5`, `1:30` or `1:01:30.25` (seconds, minutes:seconds, hours:minutes:seconds).
+pub fn parse_time(s: &str) -> anyhow::Result<Duration> {
+    let mut seconds = 0.0;
+    for (idx, part) in s.split(':').enumerate() {
+        let value = part.parse::<f64>().ok().filter(|v| *v >= 0.0 && v.is_finite());
+        let value = value.ok_or(anyhow::anyhow!("invalid time: {s:?} (expected seconds, m:ss or h:mm:ss)"))?;
+        if idx > 2 {
+            return Err(anyhow::anyhow!("invalid time: {s:?} (expected seconds, m:ss or h:mm:ss)"));
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Formats as `m:ss.mmm`, which `parse_time` reads back.
+pub fn format_time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+/// The frame at which `join` starts each segment, from the segment lengths in frames alone, followed
+/// by the length of the whole.
+pub fn layout(lengths: &[usize], joins: &[JoinSettings]) -> (Vec<usize>, usize) {
+    assert_eq!(joins.len(), lengths.len().saturating_sub(1));
+    let mut offsets = Vec::with_capacity(lengths.len());
+    let mut frames = 0;
+    for (idx, &length) in lengths.iter().enumerate() {
+        let crossfade = match idx {
+            0 => 0,
+            _ => ms_to_frames(joins[idx - 1].crossfade_ms).min(frames - offsets[idx - 1]).min(length),
+        };
+        offsets.push(frames - crossfade);
+        frames += length - crossfade;
+    }
+    (offsets, frames)
+}
+
+/// Concatenates `segments`, `joins[i]` describing the transition from segment `i` to `i + 1`.
+/// A crossfade overlaps the two segments and shortens the output; it is clamped so it never
+/// reaches past the start of the previous segment or the end of the next one.
+pub fn join<S: Sample>(segments: Vec<Vec<S>>, joins: &[JoinSettings]) -> Joined<S> {
+    assert_eq!(joins.len(), segments.len().saturating_sub(1));
+    let ch = CHANNELS as usize;
+    let mut joined = Joined {
+        samples: Vec::with_capacity(segments.iter().map(|s| s.len()).sum()),
+        offsets: Vec::with_capacity(segments.len()),
+        lengths: segments.iter().map(|s| s.len() / ch).collect(),
+    };
+    for (idx, segment) in segments.into_iter().enumerate() {
+        if idx == 0 {
+            joined.offsets.push(0);
+            joined.samples.extend(segment);
+            continue;
+        }
+        let settings = &joins[idx - 1];
+        let prev_frames = joined.frames() - joined.offsets[idx - 1];
+        let next_frames = segment.len() / ch;
+        let crossfade = ms_to_frames(settings.crossfade_ms).min(prev_frames).min(next_frames);
+        if crossfade > 0 {
+            let base = joined.samples.len() - crossfade * ch;
+            for frame in 0..crossfade {
+                let t = (frame as f32 + 0.5) / crossfade as f32;
+                let (gain_out, gain_in) = settings.curve.gains(t);
+                for c in 0..ch {
+                    let i = frame * ch + c;
+                    let mixed = joined.samples[base + i].to_f32() * gain_out + segment[i].to_f32() * gain_in;
+                    joined.samples[base + i] = S::from_f32(mixed);
+                }
+            }
+            joined.offsets.push(joined.frames() - crossfade);
+            joined.samples.extend_from_slice(&segment[crossfade * ch..]);
+        } else {
+            let declick = ms_to_frames(settings.declick_ms).min(prev_frames).min(next_frames);
+            let base = joined.samples.len() - declick * ch;
+            for frame in 0..declick {
+                let gain = (frame as f32 + 0.5) / declick as f32;
+                for c in 0..ch {
+                    let i = base + frame * ch + c;
+                    joined.samples[i] = S::from_f32(joined.samples[i].to_f32() * (1.0 - gain));
+                }
+            }
+            joined.offsets.push(joined.frames());
+            let start = joined.samples.len();
+            joined.samples.extend(segment);
+            for frame in 0..declick {
+                let gain = (frame as f32 + 0.5) / declick as f32;
+                for c in 0..ch {
+                    let i = start + frame * ch + c;
+                    joined.samples[i] = S::from_f32(joined.samples[i].to_f32() * gain);
+                }
+            }
+        }
+    }
+    joined
+}
@@ -0,0 +1,520 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use rayon::prelude::*;
+use serde_json::{Map, Value};
+
+use crate::encoder::{AudioInfo, OutputFormat};
+use crate::hash::{to_hex, Md5, Sha256};
+use crate::metadata::ENCODER;
+
+pub const OUTPUT_MANIFEST: &str = "manifest.json";
+// next to the format directories, so moving files into place is a rename on the same filesystem;
+// every format stages in its own subdirectory, so runs of different formats don't meet
+const STAGING_DIR: &str = ".dfbhd-staging";
+const READ_BUFFER_SIZE: usize = 1 << 16;
+// WAV metadata chunks (tags, cue points, labels) come before the sample data
+const HEADER_BYTES: u64 = 1 << 20;
+
+/// What happens to files in the output directory that an earlier run didn't write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Existing {
+    // stop before writing anything
+    #[default]
+    Refuse,
+    Overwrite,
+    // keep every existing file, ours included
+    Skip,
+}
+
+/// A file a run exports, relative to the format directory, with what it's made of.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    // the SBF
+    pub source: String,
+    // full segment names, in order
+    pub segments: Vec<String>,
+    // the build key of its track
+    pub key: String,
+}
+
+/// A file in `manifest.json`: what it was made of, its checksum and, for audio files, what the
+/// header says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub file: OutputFile,
+    pub sha256: Option<String>,
+    pub audio: Option<AudioInfo>,
+}
+
+impl ManifestEntry {
+    fn from_json(v: &Value) -> anyhow::Result<Self> {
+        let string = |key: &str| v.get(key).and_then(|s| s.as_str()).map(|s| s.to_owned());
+        let number = |key: &str| v.get(key).and_then(|n| n.as_u64());
+        let path = PathBuf::from(string("path").ok_or(anyhow!("file without \"path\": {v}"))?);
+        // never let a manifest point outside the directory
+        if path.is_absolute() || path.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            return Err(anyhow!("invalid path {path:?}"));
+        }
+        let segments = v
+            .get("segments")
+            .and_then(|s| s.as_array())
+            .map(|s| s.iter().filter_map(|s| s.as_str()).map(|s| s.to_owned()).collect())
+            .unwrap_or_default();
+        let audio = match (number("channels"), number("sample_rate"), number("bits"), number("frames")) {
+            (Some(channels), Some(sample_rate), Some(bits), Some(frames)) => Some(AudioInfo {
+                channels: channels as u16,
+                sample_rate: sample_rate as u32,
+                bits: bits as u16,
+                frames,
+            }),
+            _ => None,
+        };
+        Ok(Self {
+            file: OutputFile {
+                path,
+                source: string("source").unwrap_or_default(),
+                segments,
+                key: string("key").unwrap_or_default(),
+            },
+            sha256: string("sha256"),
+            audio,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("path".into(), self.file.path.to_string_lossy().into());
+        obj.insert("source".into(), self.file.source.clone().into());
+        obj.insert("segments".into(), self.file.segments.clone().into());
+        if let Some(audio) = &self.audio {
+            obj.insert("frames".into(), audio.frames.into());
+            obj.insert("duration".into(), (audio.frames as f64 / audio.sample_rate as f64).into());
+            obj.insert("sample_rate".into(), audio.sample_rate.into());
+            obj.insert("channels".into(), audio.channels.into());
+            obj.insert("bits".into(), audio.bits.into());
+        }
+        if let Some(sha256) = &self.sha256 {
+            obj.insert("sha256".into(), sha256.clone().into());
+        }
+        obj.insert("key".into(), self.file.key.clone().into());
+        obj.into()
+    }
+}
+
+/// `manifest.json` of a format directory: the files dfbhd wrote there, by path.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    // of the arrangement config of the run
+    pub config_sha256: Option<String>,
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    /// The manifest in `dir`, empty if there is none.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(OUTPUT_MANIFEST);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path).context(format!("couldn't read {path:?}"))?;
+        Self::from_json(&content).context(format!("{path:?} isn't an output manifest, move it out of the way"))
+    }
+
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_str(content)?;
+        let files = value.get("files").and_then(|f| f.as_array()).ok_or(anyhow!("missing \"files\""))?;
+        Ok(Self {
+            config_sha256: value.get("config_sha256").and_then(|c| c.as_str()).map(|c| c.to_owned()),
+            files: files
+                .iter()
+                .map(|f| ManifestEntry::from_json(f).map(|e| (e.file.path.clone(), e)))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    pub fn to_json(&self, format: OutputFormat) -> String {
+        let mut obj = Map::new();
+        obj.insert("tool".into(), ENCODER.into());
+        obj.insert("format".into(), format.extension().into());
+        if let Some(config) = &self.config_sha256 {
+            obj.insert("config_sha256".into(), config.clone().into());
+        }
+        obj.insert("files".into(), self.files.values().map(|e| e.to_json()).collect::<Vec<_>>().into());
+        serde_json::to_string_pretty(&Value::Object(obj)).unwrap() + "\n"
+    }
+}
+
+/// SHA-256 of the file at `path`, read a piece at a time so large renders needn't fit in memory.
+pub fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, File::open(path)?);
+    let mut sha = Sha256::default();
+    loop {
+        let piece = reader.fill_buf()?;
+        if piece.is_empty() {
+            return Ok(to_hex(&sha.finish()));
+        }
+        let len = piece.len();
+        sha.update(piece);
+        reader.consume(len);
+    }
+}
+
+// the first bytes of the file at `path`, enough for any header we write
+fn read_header_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![];
+    File::open(path)?.take(HEADER_BYTES).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Everything an exported track depends on, hashed: the tool version plus whatever is `add`ed.
+/// A track whose key didn't change since the run that wrote its files isn't exported again.
+#[derive(Clone)]
+pub struct BuildKey(Md5);
+
+impl Default for BuildKey {
+    fn default() -> Self {
+        let mut key = Self(Md5::default());
+        key.add(env!("CARGO_PKG_VERSION"));
+        key
+    }
+}
+
+impl BuildKey {
+    pub fn add(&mut self, data: impl AsRef<[u8]>) -> &mut Self {
+        let data = data.as_ref();
+        // length first, so the boundaries between parts count
+        self.0.update(&(data.len() as u64).to_le_bytes());
+        self.0.update(data);
+        self
+    }
+
+    pub fn finish(self) -> String {
+        to_hex(&self.0.finish())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Write,
+    // a file of an earlier run with the same build key
+    Keep,
+    // a file of an earlier run
+    Replace,
+    // a file we didn't write, with `Existing::Overwrite`
+    Overwrite,
+    Skip,
+    // a file we didn't write, with `Existing::Refuse`
+    Conflict,
+    // a file of an earlier run this run doesn't produce
+    Remove,
+}
+
+impl Action {
+    pub fn writes(&self) -> bool {
+        matches!(self, Action::Write | Action::Replace | Action::Overwrite)
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Action::Write => "write",
+            Action::Keep => "keep",
+            Action::Replace => "replace",
+            Action::Overwrite => "overwrite",
+            Action::Skip => "skip",
+            Action::Conflict => "conflict",
+            Action::Remove => "remove",
+        })
+    }
+}
+
+/// What a run does to every file of the output directory it touches, by path relative to it.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    files: BTreeMap<PathBuf, OutputFile>,
+    pub actions: BTreeMap<PathBuf, Action>,
+}
+
+impl Plan {
+    pub fn conflicts(&self) -> Vec<&Path> {
+        self.actions.iter().filter(|(_, a)| **a == Action::Conflict).map(|(p, _)| p.as_path()).collect()
+    }
+
+    /// Errors on conflicts with files in `dir`.
+    pub fn check(&self, dir: &Path) -> anyhow::Result<()> {
+        let conflicts = self.conflicts();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        let list = conflicts.iter().take(5).map(|p| p.to_string_lossy()).collect::<Vec<_>>().join(", ");
+        let more = if conflicts.len() > 5 { format!(" and {} more", conflicts.len() - 5) } else { String::new() };
+        Err(anyhow!(
+            "{} files in {dir:?} weren't written by dfbhd: {list}{more} (use --overwrite or --skip-existing)",
+            conflicts.len()
+        ))
+    }
+
+    /// Whether any of `files` has to be written, i.e. its track has to be exported.
+    pub fn writes_any(&self, files: &[OutputFile]) -> bool {
+        files.iter().any(|f| self.actions.get(&f.path).is_some_and(|a| a.writes()))
+    }
+
+    /// Keeps the files of earlier runs this one doesn't produce, for a run over some of the tracks.
+    pub fn keep_stale(&mut self) {
+        self.actions.retain(|_, action| *action != Action::Remove);
+    }
+
+    /// Leaves `files` as they are, for a track that failed: whatever it staged is dropped with
+    /// the staging directory and its files from earlier runs stay.
+    pub fn abandon(&mut self, files: &[OutputFile]) {
+        for file in files {
+            self.actions.remove(&file.path);
+            self.files.remove(&file.path);
+        }
+    }
+
+    pub fn count(&self, action: Action) -> usize {
+        self.actions.values().filter(|a| **a == action).count()
+    }
+}
+
+/// The directory a format is exported to (`<output dir>/<ext>`). Runs are written to a staging
+/// directory and moved into place once complete; files are only ever replaced or removed if an
+/// earlier run wrote them, as recorded in its `manifest.json`.
+pub struct OutputDir {
+    dir: PathBuf,
+    staging: PathBuf,
+    format: OutputFormat,
+    existing: Existing,
+    // export every track, whatever the build keys say
+    force: bool,
+    // the files earlier runs wrote
+    manifest: Manifest,
+}
+
+impl OutputDir {
+    pub fn open(output_dir: &Path, format: OutputFormat, existing: Existing, force: bool) -> anyhow::Result<Self> {
+        let dir = output_dir.join(format.extension());
+        let manifest = Manifest::load(&dir)?;
+        Ok(Self {
+            dir,
+            staging: output_dir.join(STAGING_DIR),
+            format,
+            existing,
+            force,
+            manifest,
+        })
+    }
+
+    /// Records the checksum of the arrangement config at `path` in the manifest.
+    pub fn record_config(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.manifest.config_sha256 = match path.exists() {
+            true => Some(file_sha256(path).context(format!("couldn't read {path:?}"))?),
+            false => None,
+        };
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where exporters write this run: a directory laid out like the output directory, so
+    /// files go to `<staging>/<ext>/...`.
+    pub fn staging(&self) -> &Path {
+        &self.staging
+    }
+
+    /// What writing `files` does; an error when two of them have the same path.
+    pub fn plan(&self, files: impl IntoIterator<Item = OutputFile>) -> anyhow::Result<Plan> {
+        let files = files.into_iter().collect::<Vec<_>>();
+        // a file with the build key of this run is only kept if it wasn't changed since
+        let unchanged = files
+            .par_iter()
+            .map(|file| match self.manifest.files.get(&file.path) {
+                Some(entry) if !self.force && entry.file.key == file.key => {
+                    entry.sha256.as_ref().is_some_and(|sha| file_sha256(&self.dir.join(&file.path)).is_ok_and(|actual| actual == *sha))
+                }
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        let mut actions = BTreeMap::new();
+        let mut planned = BTreeMap::new();
+        for (file, unchanged) in files.into_iter().zip(unchanged) {
+            if planned.contains_key(&file.path) {
+                return Err(anyhow!("more than one track would write {:?}", self.dir.join(&file.path)));
+            }
+            let exists = self.dir.join(&file.path).symlink_metadata().is_ok();
+            let owned = self.manifest.files.get(&file.path);
+            let action = match (exists, self.existing) {
+                (false, _) => Action::Write,
+                (true, Existing::Skip) => Action::Skip,
+                (true, _) if unchanged => Action::Keep,
+                (true, _) if owned.is_some() => Action::Replace,
+                (true, Existing::Refuse) => Action::Conflict,
+                (true, Existing::Overwrite) => Action::Overwrite,
+            };
+            actions.insert(file.path.clone(), action);
+            planned.insert(file.path.clone(), file);
+        }
+        for file in self.manifest.files.keys() {
+            if !actions.contains_key(file) && self.dir.join(file).exists() {
+                actions.insert(file.clone(), Action::Remove);
+            }
+        }
+        Ok(Plan { files: planned, actions })
+    }
+
+    // this run's part of the staging directory
+    fn staged(&self) -> PathBuf {
+        self.staging.join(self.format.extension())
+    }
+
+    /// Errors on conflicts, then sets up an empty staging directory.
+    pub fn stage(&self, plan: &Plan) -> anyhow::Result<()> {
+        plan.check(&self.dir)?;
+        let staged = self.staged();
+        let _ = std::fs::remove_dir_all(&staged);
+        std::fs::create_dir_all(&staged).context(format!("couldn't create {staged:?}"))
+    }
+
+    /// Moves the staged files into place, removes the files of earlier runs this one didn't
+    /// produce (unless `remove_stale` is off, after a partial run) and records what is ours
+    /// with checksums in the manifest. Every staged file is checked before the first is moved and
+    /// the manifest is replaced last.
+    pub fn commit(&mut self, plan: &Plan, remove_stale: bool) -> anyhow::Result<()> {
+        let staged = self.staged();
+        let entries = plan
+            .actions
+            .par_iter()
+            .filter(|(_, action)| action.writes())
+            .map(|(file, _)| {
+                let from = staged.join(file);
+                let sha = file_sha256(&from).context(format!("couldn't read {from:?}"))?;
+                let audio = match file.extension().is_some_and(|ext| ext == self.format.extension()) {
+                    true => {
+                        let header = read_header_bytes(&from).context(format!("couldn't read {from:?}"))?;
+                        Some(self.format.read_info(&header).context(format!("invalid output {from:?}"))?)
+                    }
+                    false => None,
+                };
+                Ok((file.clone(), ManifestEntry { file: plan.files[file].clone(), sha256: Some(sha), audio }))
+            })
+            .collect::<anyhow::Result<BTreeMap<_, _>>>();
+        let mut entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.discard();
+                return Err(e);
+            }
+        };
+        std::fs::create_dir_all(&self.dir).context(format!("couldn't create {:?}", self.dir))?;
+        let result = (|| -> anyhow::Result<()> {
+            for (file, action) in &plan.actions {
+                let target = self.dir.join(file);
+                match action {
+                    Action::Write | Action::Replace | Action::Overwrite => {
+                        let from = staged.join(file);
+                        if let Some(parent) = target.parent() {
+                            std::fs::create_dir_all(parent).context(format!("couldn't create {parent:?}"))?;
+                        }
+                        std::fs::rename(&from, &target).context(format!("couldn't move {from:?} to {target:?}"))?;
+                        let entry = entries.remove(file).expect("every written file has an entry");
+                        self.manifest.files.insert(file.clone(), entry);
+                    }
+                    Action::Remove if remove_stale => {
+                        std::fs::remove_file(&target).context(format!("couldn't remove {target:?}"))?;
+                        self.manifest.files.remove(file);
+                        remove_empty_parents(&self.dir, &target);
+                    }
+                    Action::Keep | Action::Skip | Action::Conflict | Action::Remove => {}
+                }
+            }
+            Ok(())
+        })();
+        // whatever got moved is ours, even if the rest failed; forget what was deleted by hand
+        self.manifest.files.retain(|file, _| self.dir.join(file).exists());
+        let written = self.write_manifest();
+        self.discard();
+        result.and(written)
+    }
+
+    // through a temporary file, so the manifest is either the old or the new one
+    fn write_manifest(&self) -> anyhow::Result<()> {
+        let manifest = self.dir.join(OUTPUT_MANIFEST);
+        let temporary = self.staged().join(OUTPUT_MANIFEST);
+        std::fs::write(&temporary, self.manifest.to_json(self.format)).context(format!("couldn't write {temporary:?}"))?;
+        std::fs::rename(&temporary, &manifest).context(format!("couldn't write {manifest:?}"))
+    }
+
+    pub fn discard(&self) {
+        let _ = std::fs::remove_dir_all(self.staged());
+        // only once no other format is staged there
+        let _ = std::fs::remove_dir(&self.staging);
+    }
+}
+
+// directories emptied by removing `file`, up to `dir`
+fn remove_empty_parents(dir: &Path, file: &Path) {
+    let mut parent = file.parent();
+    while let Some(p) = parent {
+        if p == dir || std::fs::remove_dir(p).is_err() {
+            break;
+        }
+        parent = p.parent();
+    }
+}
+
+/// How a file listed in a manifest compares to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Ok,
+    Missing,
+    Changed,
+    // listed by a manifest from before checksums
+    NoChecksum,
+}
+
+impl Display for Verified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Verified::Ok => "ok",
+            Verified::Missing => "missing",
+            Verified::Changed => "changed",
+            Verified::NoChecksum => "unchecked",
+        })
+    }
+}
+
+/// Checks every file listed in the manifest of the format directory `dir` against its checksum.
+pub fn verify(dir: &Path) -> anyhow::Result<Vec<(PathBuf, Verified)>> {
+    if !dir.join(OUTPUT_MANIFEST).exists() {
+        return Err(anyhow!("no {OUTPUT_MANIFEST} in {dir:?}"));
+    }
+    let manifest = Manifest::load(dir)?;
+    manifest
+        .files
+        .par_iter()
+        .map(|(file, entry)| {
+            let path = dir.join(file);
+            let verified = match &entry.sha256 {
+                _ if !path.exists() => Verified::Missing,
+                None => Verified::NoChecksum,
+                Some(sha) => match file_sha256(&path) {
+                    Ok(actual) if actual == *sha => Verified::Ok,
+                    Ok(_) => Verified::Changed,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Verified::Missing,
+                    Err(e) => return Err(e).context(format!("couldn't read {path:?}")),
+                },
+            };
+            Ok((file.clone(), verified))
+        })
+        .collect()
+}
@@ -0,0 +1,252 @@
+use std::f64::consts::PI;
+
+use anyhow::anyhow;
+
+use crate::encoder::{Encoder, TrackInfo};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quality {
+    Fast,
+    #[default]
+    Standard,
+    High,
+}
+
+impl Quality {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "fast" => Ok(Quality::Fast),
+            "standard" => Ok(Quality::Standard),
+            "high" => Ok(Quality::High),
+            _ => Err(anyhow!("unknown resampler quality: {s:?} (expected fast, standard or high)")),
+        }
+    }
+
+    // (taps per phase, Kaiser beta, cutoff as a fraction of the lower Nyquist frequency)
+    fn filter(&self) -> (usize, f64, f64) {
+        match self {
+            Quality::Fast => (16, 6.0, 0.85),
+            Quality::Standard => (48, 8.0, 0.91),
+            Quality::High => (128, 10.0, 0.95),
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-17 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Band-limited rational resampler: a Kaiser windowed sinc evaluated at `up` phases (polyphase),
+/// converting interleaved samples from `from` to `to` Hz. Output frame `n` lies exactly at input
+/// time `n * from / to`, so there is no delay to compensate.
+pub struct Resampler {
+    channels: usize,
+    up: u64,
+    down: u64,
+    taps: usize,
+    // taps of phase `p` at `p * taps..(p + 1) * taps`
+    table: Vec<f64>,
+    // interleaved input, frame `b` is padded input frame `b + consumed`
+    buffer: Vec<f64>,
+    consumed: u64,
+    input_frames: u64,
+    // next output frame
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(channels: u16, from: u32, to: u32, quality: Quality) -> Self {
+        let divisor = gcd(from as u64, to as u64);
+        let (up, down) = (to as u64 / divisor, from as u64 / divisor);
+        let (taps, beta, rolloff) = quality.filter();
+        // cutoff relative to the input Nyquist frequency; downsampling needs it lower and the
+        // filter longer by the same factor
+        let cutoff = rolloff * (up as f64 / down as f64).min(1.0);
+        let taps = ((taps as f64 * rolloff / cutoff).ceil() as usize).next_multiple_of(2);
+        let half = (taps / 2) as f64;
+        let mut table = Vec::with_capacity(up as usize * taps);
+        for phase in 0..up {
+            let frac = phase as f64 / up as f64;
+            let start = table.len();
+            for k in 0..taps {
+                let d = k as f64 - (half - 1.0) - frac;
+                let x = PI * cutoff * d;
+                let sinc = if x.abs() < 1e-12 { 1.0 } else { x.sin() / x };
+                let w = (1.0 - (d / half).powi(2)).max(0.0);
+                table.push(sinc * bessel_i0(beta * w.sqrt()) / bessel_i0(beta));
+            }
+            // unity gain at DC for every phase
+            let sum = table[start..].iter().sum::<f64>();
+            table[start..].iter_mut().for_each(|t| *t /= sum);
+        }
+        Self {
+            channels: channels as usize,
+            up,
+            down,
+            taps,
+            table,
+            buffer: vec![0.0; (taps / 2 - 1) * channels as usize],
+            consumed: 0,
+            input_frames: 0,
+            produced: 0,
+        }
+    }
+
+    /// How many frames `input_frames` frames become.
+    pub fn output_frames(&self, input_frames: u64) -> u64 {
+        (input_frames * self.up).div_ceil(self.down)
+    }
+
+    fn run(&mut self, limit: u64, out: &mut Vec<f64>) {
+        let ch = self.channels;
+        let buffered = self.consumed + (self.buffer.len() / ch) as u64;
+        while self.produced < limit {
+            let t = self.produced * self.down;
+            let (i, phase) = (t / self.up, (t % self.up) as usize);
+            if i + self.taps as u64 > buffered {
+                break;
+            }
+            let taps = &self.table[phase * self.taps..(phase + 1) * self.taps];
+            let base = (i - self.consumed) as usize * ch;
+            for c in 0..ch {
+                let mut acc = 0.0;
+                for (k, tap) in taps.iter().enumerate() {
+                    acc += tap * self.buffer[base + k * ch + c];
+                }
+                out.push(acc);
+            }
+            self.produced += 1;
+        }
+        let keep_from = (self.produced * self.down / self.up).min(buffered);
+        let drop = (keep_from - self.consumed) as usize;
+        self.buffer.drain(..drop * ch);
+        self.consumed = keep_from;
+    }
+
+    /// Takes interleaved samples and appends whatever output they complete to `out`.
+    pub fn process_f64(&mut self, input: &[f64], out: &mut Vec<f64>) {
+        self.buffer.extend_from_slice(input);
+        self.input_frames += (input.len() / self.channels) as u64;
+        self.run(u64::MAX, out);
+    }
+
+    /// Flushes the remaining output; the total is `output_frames` of everything passed in.
+    pub fn finish_f64(&mut self, out: &mut Vec<f64>) {
+        self.buffer.extend(std::iter::repeat_n(0.0, self.taps / 2 * self.channels));
+        let total = self.output_frames(self.input_frames);
+        self.run(total, out);
+    }
+
+    pub fn process(&mut self, input: &[i16], out: &mut Vec<i16>) {
+        let input = input.iter().map(|&s| s as f64).collect::<Vec<_>>();
+        let mut resampled = vec![];
+        self.process_f64(&input, &mut resampled);
+        out.extend(resampled.into_iter().map(to_i16));
+    }
+
+    pub fn finish(&mut self, out: &mut Vec<i16>) {
+        let mut resampled = vec![];
+        self.finish_f64(&mut resampled);
+        out.extend(resampled.into_iter().map(to_i16));
+    }
+}
+
+fn to_i16(s: f64) -> i16 {
+    s.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+/// Conversion applied to exported tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resample {
+    pub rate: u32,
+    pub quality: Quality,
+}
+
+impl Resample {
+    pub fn parse_rate(s: &str) -> anyhow::Result<u32> {
+        match s {
+            "22050" | "44100" | "48000" => Ok(s.parse()?),
+            _ => Err(anyhow!("unsupported sample rate: {s:?} (expected 22050, 44100 or 48000)")),
+        }
+    }
+
+    /// Rewrites the rate, length and marker positions of `info` to the converted track.
+    pub fn convert_info(&self, info: &mut TrackInfo) {
+        let resampler = Resampler::new(info.channels, info.sample_rate, self.rate, self.quality);
+        info.frames = resampler.output_frames(info.frames);
+        for marker in &mut info.markers {
+            let end = resampler.output_frames(marker.start + marker.frames);
+            marker.start = resampler.output_frames(marker.start);
+            marker.frames = end - marker.start;
+        }
+        info.sample_rate = self.rate;
+    }
+
+    pub fn wrap(&self, channels: u16, from: u32, inner: Box<dyn Encoder>) -> Box<dyn Encoder> {
+        Box::new(ResamplingEncoder {
+            inner,
+            resampler: Resampler::new(channels, from, self.rate, self.quality),
+            buffer: vec![],
+            float: None,
+        })
+    }
+}
+
+struct ResamplingEncoder {
+    inner: Box<dyn Encoder>,
+    resampler: Resampler,
+    buffer: Vec<i16>,
+    // output of float input, `None` until `write_float` is used
+    float: Option<Vec<f64>>,
+}
+
+impl Encoder for ResamplingEncoder {
+    fn write_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        self.inner.write_samples(&self.buffer)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        let input = samples.iter().map(|&s| s as f64).collect::<Vec<_>>();
+        let out = self.float.get_or_insert_default();
+        out.clear();
+        self.resampler.process_f64(&input, out);
+        let out = out.iter().map(|&s| s as f32).collect::<Vec<_>>();
+        self.inner.write_float(&out)
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        match self.float.take() {
+            Some(mut out) => {
+                out.clear();
+                self.resampler.finish_f64(&mut out);
+                let out = out.iter().map(|&s| s as f32).collect::<Vec<_>>();
+                self.inner.write_float(&out)?;
+            }
+            None => {
+                self.buffer.clear();
+                self.resampler.finish(&mut self.buffer);
+                self.inner.write_samples(&self.buffer)?;
+            }
+        }
+        self.inner.finish()
+    }
+}
@@ -1,17 +1,29 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use itertools::Itertools;
 use libc::{MAP_PRIVATE, PROT_READ};
-use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
 use std::os::fd::AsRawFd;
 use std::path::Path;
 use std::ptr::null_mut;
 
 use crate::array_transmute;
+use crate::encoder::{ExportOptions, TrackInfo};
+use crate::enhance::{decode_blocks, DecodeMode, Enhance};
+use crate::markers::Marker;
+use crate::metadata::MetadataFile;
+use crate::segments::export_segments;
+
+pub const SAMPLE_RATE: u32 = 22050;
+pub const CHANNELS: u16 = 2;
+// the largest `scale1` that `upscale_pcm` can divide by
+const MAX_SCALE: u8 = 14;
+
+// the music files of the original game, in the order bare track names are looked up
+pub const MUSIC_FILES: [&str; 3] = ["menumus.sbf", "gamemus.sbf", "EXP1.sbf"];
 
 pub struct SBF {
+    pub name: String,
     pub content: &'static [u8],
     pub header: SBFHeader,
     pub chunks: Vec<SBFIndexEntry>,
@@ -20,9 +32,14 @@ pub struct SBF {
 
 impl SBF {
     pub fn from_file(file: &Path) -> anyhow::Result<Self> {
+        let path = file;
         let content = unsafe {
             let file = File::open(file).context(format!("couldn't open {file:?}"))?;
             let size = file.metadata()?.len();
+            // mapping nothing fails, and there'd be no header anyway
+            if size < size_of::<SBFHeader>() as u64 {
+                return Err(anyhow!("too short for an SBF header")).context(format!("{path:?} is not an SBF file"));
+            }
             let ptr = libc::mmap(
                 null_mut(),
                 size as _,
@@ -31,27 +48,27 @@ impl SBF {
                 file.as_raw_fd(),
                 0,
             );
+            if ptr == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error()).context(format!("couldn't map {path:?}"));
+            }
             std::slice::from_raw_parts(ptr as *const u8, size as _)
         };
         let header =
             array_transmute::<_, SBFHeader>(&content[0..size_of::<SBFHeader>()])[0].clone();
-        let index: &[SBFIndexEntryBin] = array_transmute(
-            &content[header.index_offset as usize
-                ..(header.index_offset as usize
-                    + header.index_count as usize * size_of::<SBFIndexEntryBin>())],
-        );
-        index.iter().for_each(|e| {
-            // assert_eq!(e.z1, 0); // non zero for only one chunk (VALIANT0)
-            assert_eq!(e.z2, 0);
-            assert_eq!(e.z3, 0);
-            assert_eq!(e.block_size, 4104);
-            assert_eq!(e.size % e.block_size, 0);
-        });
+        let index = match check_index(content, &header) {
+            Ok(index) => index,
+            Err(e) => {
+                unsafe {
+                    libc::munmap(content.as_ptr() as _, content.len());
+                }
+                return Err(e.context(format!("{file:?} is not an SBF file")));
+            }
+        };
         let chunks = index
             .iter()
             .map(|index| {
-                let (ident, suffix) = if index.ident[0] == 'm' as u8 {
-                    let split_point = index.ident.iter().take_while(|&&b| b != 'a' as u8).count();
+                let (ident, suffix) = if index.ident[0] == b'm' {
+                    let split_point = index.ident.iter().take_while(|&&b| b != b'a').count();
                     (
                         index
                             .ident
@@ -110,12 +127,46 @@ impl SBF {
             .collect::<HashMap<_, _>>();
 
         Ok(Self {
+            name: file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
             content,
             header,
             chunks,
             grouped_chunks,
         })
     }
+
+    /// The raw blocks of a segment.
+    pub fn segment_data(&self, e: &SBFIndexEntry) -> &[u8] {
+        &self.content[e.start as usize..(e.start + e.size) as usize]
+    }
+
+    pub fn segment_blocks(&self, e: &SBFIndexEntry) -> &[SBFChunkData] {
+        array_transmute(self.segment_data(e))
+    }
+
+    /// Length of a segment from its block headers, without decoding it.
+    pub fn segment_frames(&self, e: &SBFIndexEntry) -> u64 {
+        self.segment_blocks(e).iter().map(|b| b.size as u64).sum::<u64>() / CHANNELS as u64
+    }
+
+    pub fn decode_segment(&self, e: &SBFIndexEntry) -> Vec<i16> {
+        let blocks = self.segment_blocks(e);
+        let mut pcm_data = Vec::with_capacity(blocks.iter().map(|b| b.size as usize).sum());
+        for block in blocks {
+            for &b in &block.content[0..block.size as usize] {
+                pcm_data.push(upscale_pcm(b, block.scale1));
+            }
+        }
+        pcm_data
+    }
+
+    pub fn decode_segment_enhanced(&self, e: &SBFIndexEntry, settings: &Enhance) -> Vec<f32> {
+        decode_blocks(self.segment_blocks(e), settings)
+    }
+
+    pub fn segment(&self, ident: &str, suffix: &str) -> Option<&SBFIndexEntry> {
+        self.grouped_chunks.get(ident)?.iter().find(|e| e.suffix == suffix)
+    }
 }
 
 impl Drop for SBF {
@@ -126,82 +177,120 @@ impl Drop for SBF {
     }
 }
 
-pub fn process_file(file: &Path, output: &Path) -> anyhow::Result<()> {
-    let sbf = SBF::from_file(file)?;
-    sbf.grouped_chunks.par_iter().for_each(|(prefix, es)| {
-        let wav_path = output.join("wav").join(format!("{prefix}.wav"));
-        let mut f = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&wav_path)
-            .unwrap();
-        let mut total_size = 0;
-        for e in es.iter() {
-            let segment_blob = &sbf.content[e.start as usize..(e.start + e.size) as usize];
-            for chunk in array_transmute::<_, SBFChunkData>(segment_blob) {
-                total_size += chunk.size * 2;
-            }
+// The index of `content`, checked so that every entry lies within the file and consists of whole
+// blocks whose headers decode.
+fn check_index<'a>(content: &'a [u8], header: &SBFHeader) -> anyhow::Result<&'a [SBFIndexEntryBin]> {
+    let offset = header.index_offset as usize;
+    let end = offset + header.index_count as usize * size_of::<SBFIndexEntryBin>();
+    if !offset.is_multiple_of(4) || end > content.len() {
+        return Err(anyhow!("index at {offset} with {} entries doesn't fit the file", header.index_count));
+    }
+    let index: &[SBFIndexEntryBin] = array_transmute(&content[offset..end]);
+    for e in index {
+        let name = String::from_utf8_lossy(&e.ident);
+        let name = name.trim_end_matches('\0');
+        // z1 is non zero for only one chunk (VALIANT0)
+        if e.z2 != 0 || e.z3 != 0 {
+            return Err(anyhow!("entry {name:?} has unknown fields set"));
         }
-        write_wav_header(&mut f, total_size).unwrap();
-        let mut parsed_data = arrayvec::ArrayVec::<_, 8192>::new();
-        for e in es.iter() {
-            let segment_blob = &sbf.content[e.start as usize..(e.start + e.size) as usize];
-            for chunk in array_transmute::<_, SBFChunkData>(segment_blob) {
-                parsed_data.clear();
-                for &b in &chunk.content[0..chunk.size as usize] {
-                    let bytes = upscale_pcm(b, chunk.scale1).to_le_bytes();
-                    parsed_data.extend(bytes);
-                }
-                f.write_all(parsed_data.as_slice()).unwrap();
+        if e.block_size != 4104 || !e.size.is_multiple_of(e.block_size) {
+            return Err(anyhow!("entry {name:?} isn't made of 4104 byte blocks"));
+        }
+        if !e.start.is_multiple_of(4) || e.start as usize + e.size as usize > content.len() {
+            return Err(anyhow!("entry {name:?} lies outside the file"));
+        }
+        let blocks: &[SBFChunkData] = array_transmute(&content[e.start as usize..e.start as usize + e.size as usize]);
+        for (idx, block) in blocks.iter().enumerate() {
+            if block.size as usize > block.content.len() {
+                return Err(anyhow!("block {idx} of entry {name:?} claims {} bytes", block.size));
+            }
+            if block.scale1 > MAX_SCALE {
+                return Err(anyhow!("block {idx} of entry {name:?} has scale {}", block.scale1));
             }
         }
-        f.flush().unwrap();
-    });
-    Ok(())
+    }
+    Ok(index)
 }
 
-pub fn write_wav_header(writer: &mut File, total_size: u32) -> anyhow::Result<()> {
-    let num_channels: u16 = 2;
-    let bits_per_sample: u16 = 16;
-    let sample_rate: u32 = 22050;
-    let block_align = num_channels * (bits_per_sample / 8);
-    let byte_rate = sample_rate * u32::from(block_align);
-
-    writer.write_all(b"RIFF")?;
-    writer.write_all(&(36 + total_size).to_le_bytes())?;
-    writer.write_all(b"WAVE")?;
-
-    writer.write_all(b"fmt ")?;
-    writer.write_all(&16u32.to_le_bytes())?;
-    writer.write_all(&1u16.to_le_bytes())?;
-    writer.write_all(&num_channels.to_le_bytes())?;
-    writer.write_all(&sample_rate.to_le_bytes())?;
-    writer.write_all(&byte_rate.to_le_bytes())?;
-    writer.write_all(&block_align.to_le_bytes())?;
-    writer.write_all(&bits_per_sample.to_le_bytes())?;
-
-    writer.write_all(b"data")?;
-    writer.write_all(&total_size.to_le_bytes())?;
-
-    Ok(())
+/// Exports the track `prefix` of `sbf`, made of the segments `es` in SBF order, to `output/<ext>`
+/// under the output `name` (see `arrangement::output_name`).
+pub fn export_prefix(
+    sbf: &SBF,
+    prefix: &str,
+    es: &[SBFIndexEntry],
+    name: &str,
+    output: &Path,
+    metadata: &MetadataFile,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let format = options.format;
+    if options.segment_files.segments() {
+        let dir = output.join(format.extension()).join(name);
+        export_segments(sbf, prefix, &es.iter().collect::<Vec<_>>(), &dir, metadata, options)?;
+    }
+    if !options.segment_files.tracks() {
+        return Ok(());
+    }
+    let file_name = if options.titled_names {
+        metadata.titles.file_name(&sbf.name, prefix, name)
+    } else {
+        name.to_owned()
+    };
+    let path = output.join(format.extension()).join(format!("{file_name}.{}", format.extension()));
+    let mut markers = vec![];
+    let mut total_frames = 0;
+    for e in es.iter() {
+        let frames = sbf.segment_frames(e);
+        markers.push(Marker {
+            name: format!("{}{}", e.ident, e.suffix),
+            start: total_frames,
+            frames,
+        });
+        total_frames += frames;
+    }
+    let segments = es.iter().map(|e| format!("{}{}", e.ident, e.suffix)).collect();
+    let mut info = TrackInfo {
+        metadata: metadata.resolve(&sbf.name, prefix, segments),
+        channels: CHANNELS,
+        sample_rate: SAMPLE_RATE,
+        frames: total_frames,
+        sample_format: options.sample_format,
+        markers,
+    };
+    let mut encoder = options.create(&path, &mut info)?;
+    options.sidecars.write(&path, &info)?;
+    let mut parsed_data = arrayvec::ArrayVec::<_, 4096>::new();
+    for e in es.iter() {
+        if let DecodeMode::Enhanced(settings) = &options.decoder {
+            encoder.write_float(&sbf.decode_segment_enhanced(e, settings))?;
+            continue;
+        }
+        for chunk in sbf.segment_blocks(e) {
+            parsed_data.clear();
+            for &b in &chunk.content[0..chunk.size as usize] {
+                parsed_data.push(upscale_pcm(b, chunk.scale1));
+            }
+            encoder.write_samples(parsed_data.as_slice())?;
+        }
+    }
+    encoder.finish()
 }
 
 pub fn upscale_pcm(b: u8, scale: u8) -> i16 {
     let b = b as i16 - 128;
     let b = b * 256;
-    let b = b / 2i16.pow(scale as u32) / 2;
-    b
+    b / 2i16.pow(scale as u32) / 2
 }
 
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub struct SBFHeader {
-    magic: [u8; 4],
-    i1: u32,
-    i2: u32,
-    i3: u32,
-    index_offset: u32,
-    index_count: u32,
+    pub magic: [u8; 4],
+    pub i1: u32,
+    pub i2: u32,
+    pub i3: u32,
+    pub index_offset: u32,
+    pub index_count: u32,
 }
 
 // ....................................
@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_json::json;
+
+use crate::encoder::{ExportOptions, OutputFormat, TrackInfo};
+use crate::enhance::DecodeMode;
+use crate::metadata::MetadataFile;
+use crate::sbf::{SBFIndexEntry, CHANNELS, SAMPLE_RATE, SBF};
+
+pub const SEGMENT_MANIFEST: &str = "segments.json";
+
+/// Whether the processors write full tracks, single segments or both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SegmentFiles {
+    #[default]
+    Off,
+    Also,
+    Only,
+}
+
+impl SegmentFiles {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(SegmentFiles::Off),
+            "also" => Ok(SegmentFiles::Also),
+            "only" => Ok(SegmentFiles::Only),
+            _ => Err(anyhow!("unknown segment mode: {s:?} (expected off, also or only)")),
+        }
+    }
+
+    pub fn tracks(&self) -> bool {
+        *self != SegmentFiles::Only
+    }
+
+    pub fn segments(&self) -> bool {
+        *self != SegmentFiles::Off
+    }
+}
+
+// `<suffix>.<ext>`
+fn segment_file_name(suffix: &str, format: OutputFormat) -> String {
+    format!("{suffix}.{}", format.extension())
+}
+
+/// The files `export_segments` writes to its directory for segments with `suffixes`.
+pub fn segment_files<'a>(suffixes: impl IntoIterator<Item = &'a str>, format: OutputFormat) -> Vec<String> {
+    let mut files = suffixes.into_iter().map(|suffix| segment_file_name(suffix, format)).collect::<Vec<_>>();
+    files.push(SEGMENT_MANIFEST.to_owned());
+    files
+}
+
+/// Writes every segment of `entries` (in track order) undecorated to `dir/<suffix>.<ext>`, each
+/// tagged as `<track title> - <segment>`, plus a `segments.json` manifest with the order,
+/// durations and byte ranges in the source SBF.
+pub fn export_segments(
+    sbf: &SBF,
+    ident: &str,
+    entries: &[&SBFIndexEntry],
+    dir: &Path,
+    metadata: &MetadataFile,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let format = options.format;
+    std::fs::create_dir_all(dir).context(format!("couldn't create {dir:?}"))?;
+    let mut manifest = vec![];
+    for (idx, e) in entries.iter().enumerate() {
+        let name = format!("{}{}", e.ident, e.suffix);
+        let file_name = segment_file_name(&e.suffix, format);
+        let frames = sbf.segment_frames(e);
+        let mut track = metadata.resolve(&sbf.name, ident, vec![name.to_owned()]);
+        track.title = format!("{} - {name}", track.title);
+        track.track_number = Some(idx as u32 + 1);
+        let mut info = TrackInfo {
+            metadata: track,
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            frames,
+            sample_format: options.sample_format,
+            markers: vec![],
+        };
+        let mut encoder = options.create(&dir.join(&file_name), &mut info)?;
+        match &options.decoder {
+            DecodeMode::Classic => encoder.write_samples(&sbf.decode_segment(e))?,
+            DecodeMode::Enhanced(settings) => encoder.write_float(&sbf.decode_segment_enhanced(e, settings))?,
+        }
+        encoder.finish()?;
+        manifest.push(json!({
+            "index": idx,
+            "name": name,
+            "suffix": e.suffix,
+            "file": file_name,
+            "frames": info.frames,
+            "duration": info.frames as f64 / info.sample_rate as f64,
+            "byte_start": e.start,
+            "byte_end": e.start + e.size,
+            "blocks": e.size / e.block_size,
+        }));
+    }
+    let manifest = json!({
+        "track": ident,
+        "source": sbf.name,
+        "sample_rate": options.resample.map_or(SAMPLE_RATE, |r| r.rate),
+        "channels": CHANNELS,
+        "segments": manifest,
+    });
+    let path = dir.join(SEGMENT_MANIFEST);
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest)? + "\n").context(format!("couldn't write {path:?}"))?;
+    Ok(())
+}
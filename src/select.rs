@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+
+use crate::arrangement::{qualified_key, TrackArrangement};
+use crate::sbf::SBFIndexEntry;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// A glob over track names, or a regex when written as `/.../`. Both ignore case; a glob matches
+/// the whole name, a regex anywhere in it. Patterns containing a `:` are matched against the
+/// qualified name (`EXP1.sbf:m3`), the others against the bare ident.
+#[derive(Debug, Clone)]
+pub enum TrackPattern {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+impl TrackPattern {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(regex) => Ok(TrackPattern::Regex(RegexBuilder::new(regex).case_insensitive(true).build()?)),
+            None if s.is_empty() => Err(anyhow!("empty track pattern")),
+            None => Ok(TrackPattern::Glob(Pattern::new(s)?)),
+        }
+    }
+
+    fn source(&self) -> &str {
+        match self {
+            TrackPattern::Glob(pattern) => pattern.as_str(),
+            TrackPattern::Regex(regex) => regex.as_str(),
+        }
+    }
+
+    pub fn matches(&self, file: &str, ident: &str) -> bool {
+        let name = match self.source().contains(':') {
+            true => qualified_key(file, ident),
+            false => ident.to_owned(),
+        };
+        match self {
+            TrackPattern::Glob(pattern) => pattern.matches_with(&name, MATCH_OPTIONS),
+            TrackPattern::Regex(regex) => regex.is_match(&name),
+        }
+    }
+}
+
+/// Which tracks a command works on: those matching any of `tracks` (all when empty) and none of
+/// `exclude`, from SBFs whose file name matches any of `files` (all when empty). With `tiers` only
+/// the segments whose suffix starts with one of them are used (`a020` picks `a020a`, `a020b`, ...
+/// of the mission music) and tracks without any are left out.
+#[derive(Debug, Clone, Default)]
+pub struct TrackFilter {
+    pub tracks: Vec<TrackPattern>,
+    pub exclude: Vec<TrackPattern>,
+    pub files: Vec<Pattern>,
+    pub tiers: Vec<String>,
+}
+
+impl TrackFilter {
+    /// Whether everything is selected.
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty() && self.exclude.is_empty() && self.files.is_empty() && self.tiers.is_empty()
+    }
+
+    /// The output name of a track called `name` in a full run. Tracks of only some tiers get the
+    /// tiers added (`m3.a020`), so they don't replace the whole track.
+    pub fn output_name(&self, name: &str) -> String {
+        match self.tiers.is_empty() {
+            true => name.to_owned(),
+            false => format!("{name}.{}", self.tiers.join("+")),
+        }
+    }
+
+    pub fn selects_file(&self, file: &str) -> bool {
+        self.files.is_empty() || self.files.iter().any(|p| p.matches_with(file, MATCH_OPTIONS))
+    }
+
+    /// Whether the track is selected, leaving tiers aside.
+    pub fn selects_track(&self, file: &str, ident: &str) -> bool {
+        self.selects_file(file)
+            && (self.tracks.is_empty() || self.tracks.iter().any(|p| p.matches(file, ident)))
+            && !self.exclude.iter().any(|p| p.matches(file, ident))
+    }
+
+    pub fn selects_segment(&self, suffix: &str) -> bool {
+        self.tiers.is_empty() || self.tiers.iter().any(|tier| suffix.get(..tier.len()).is_some_and(|s| s.eq_ignore_ascii_case(tier)))
+    }
+
+    /// The selected segments of a track, in order; none when the track isn't selected.
+    pub fn segments<'a>(&self, file: &str, ident: &str, entries: &'a [SBFIndexEntry]) -> Vec<&'a SBFIndexEntry> {
+        if !self.selects_track(file, ident) {
+            return vec![];
+        }
+        entries.iter().filter(|e| self.selects_segment(&e.suffix)).collect()
+    }
+
+    /// The arrangement with only the selected segments; `None` when the track isn't selected or
+    /// none of its segments are.
+    pub fn arrangement(&self, file: &str, ident: &str, arrangement: &TrackArrangement) -> Option<TrackArrangement> {
+        if !self.selects_track(file, ident) {
+            return None;
+        }
+        let order = arrangement.order.iter().filter(|suffix| self.selects_segment(suffix)).cloned().collect::<Vec<_>>();
+        if order.is_empty() {
+            return None;
+        }
+        Some(TrackArrangement { order, ..arrangement.clone() })
+    }
+}
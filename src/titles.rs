@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+
+use crate::arrangement::qualified_key;
+
+pub const DEFAULT_TITLES_PATH: &str = "track_titles.json";
+const BUNDLED: &str = include_str!("titles.json");
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackTitle {
+    pub title: Option<String>,
+    pub mission: Option<String>,
+    // menu, briefing, mission, debrief, victory, failure, credits
+    pub context: Option<String>,
+    pub intensity: Option<String>,
+    pub description: Option<String>,
+}
+
+impl TrackTitle {
+    fn from_json(obj: &Map<String, Value>) -> anyhow::Result<Self> {
+        let string = |key: &str| -> anyhow::Result<Option<String>> {
+            obj.get(key)
+                .map(|v| v.as_str().map(|s| s.to_owned()).ok_or(anyhow!("{key} must be a string, got {v}")))
+                .transpose()
+        };
+        Ok(Self {
+            title: string("title")?,
+            mission: string("mission")?,
+            context: string("context")?,
+            intensity: string("intensity")?,
+            description: string("description")?,
+        })
+    }
+
+    fn merge(&mut self, over: &TrackTitle) {
+        for (field, value) in [
+            (&mut self.title, &over.title),
+            (&mut self.mission, &over.mission),
+            (&mut self.context, &over.context),
+            (&mut self.intensity, &over.intensity),
+            (&mut self.description, &over.description),
+        ] {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+    }
+
+    // "mission, victory, high intensity"
+    pub fn summary(&self) -> String {
+        [
+            self.mission.clone(),
+            self.context.clone(),
+            self.intensity.as_ref().map(|i| format!("{i} intensity")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// Human-readable titles for SBF idents: the database bundled with the tools, with entries of
+/// `track_titles.json` (same format, keyed by bare or qualified idents) merged over it field by field.
+#[derive(Debug, Clone, Default)]
+pub struct TitleDatabase {
+    pub entries: BTreeMap<String, TrackTitle>,
+}
+
+impl TitleDatabase {
+    pub fn bundled() -> Self {
+        let mut db = Self::default();
+        db.merge_json(BUNDLED).expect("bundled titles.json is invalid");
+        db
+    }
+
+    pub fn load(override_path: &Path) -> anyhow::Result<Self> {
+        let mut db = Self::bundled();
+        if override_path.exists() {
+            let content = std::fs::read_to_string(override_path).context(format!("couldn't read {override_path:?}"))?;
+            db.merge_json(&content).context(format!("invalid title database {override_path:?}"))?;
+        }
+        Ok(db)
+    }
+
+    fn merge_json(&mut self, content: &str) -> anyhow::Result<()> {
+        let value: Value = serde_json::from_str(content)?;
+        let obj = value.as_object().ok_or(anyhow!("top level must be an object"))?;
+        for (key, v) in obj {
+            let fields = v.as_object().ok_or(anyhow!("{key} must be an object"))?;
+            let title = TrackTitle::from_json(fields).context(key.to_owned())?;
+            self.entries.entry(key.to_owned()).or_default().merge(&title);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, source: &str, ident: &str) -> Option<TrackTitle> {
+        let qualified = qualified_key(source, ident);
+        let qualified = self.entries.iter().find(|(key, _)| key.eq_ignore_ascii_case(&qualified));
+        match (self.entries.get(ident), qualified) {
+            (None, None) => None,
+            (bare, qualified) => {
+                let mut title = bare.cloned().unwrap_or_default();
+                if let Some((_, q)) = qualified {
+                    title.merge(q);
+                }
+                Some(title)
+            }
+        }
+    }
+
+    pub fn title(&self, source: &str, ident: &str) -> Option<String> {
+        self.get(source, ident)?.title
+    }
+
+    /// `<name> - <title>` with characters that are awkward in file names replaced, or just `name`
+    /// for tracks without a title.
+    pub fn file_name(&self, source: &str, ident: &str, name: &str) -> String {
+        match self.title(source, ident) {
+            Some(title) => {
+                let title = title
+                    .chars()
+                    .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                    .collect::<String>();
+                format!("{name} - {}", title.trim())
+            }
+            None => name.to_owned(),
+        }
+    }
+}
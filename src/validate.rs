@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::arrangement::{qualified_key, split_track_key, ArrangementConfig, Resolution};
+use crate::sbf::SBF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    MissingTrack { track: String },
+    UnknownFile { track: String, file: String },
+    UnknownSuffix { track: String, suffix: String },
+    DuplicateSuffix { track: String, suffix: String, count: usize },
+    OmittedSegment { track: String, suffix: String },
+    AmbiguousTrack { track: String, files: Vec<String> },
+    UnconfiguredTrack { file: String, track: String },
+    DuplicateTrack { file: String, track: String, keys: Vec<String> },
+}
+
+impl Problem {
+    // errors make the config unrenderable or ambiguous, everything else is reported as a warning
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Problem::MissingTrack { .. }
+                | Problem::UnknownFile { .. }
+                | Problem::UnknownSuffix { .. }
+                | Problem::DuplicateTrack { .. }
+        )
+    }
+
+    /// Whether the problem is with the config entry `key`.
+    pub fn concerns(&self, key: &str) -> bool {
+        match self {
+            Problem::MissingTrack { track }
+            | Problem::UnknownFile { track, .. }
+            | Problem::UnknownSuffix { track, .. }
+            | Problem::DuplicateSuffix { track, .. }
+            | Problem::OmittedSegment { track, .. }
+            | Problem::AmbiguousTrack { track, .. } => track == key,
+            Problem::DuplicateTrack { keys, .. } => keys.iter().any(|k| k == key),
+            // not in the config at all
+            Problem::UnconfiguredTrack { .. } => false,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::MissingTrack { track } => write!(f, "{track}: track not found in any SBF"),
+            Problem::UnknownFile { track, file } => write!(f, "{track}: no SBF named {file}"),
+            Problem::UnknownSuffix { track, suffix } => {
+                write!(f, "{track}: unknown segment {}{suffix}", split_track_key(track).1)
+            }
+            Problem::DuplicateSuffix { track, suffix, count } => {
+                write!(f, "{track}: segment {}{suffix} listed {count} times", split_track_key(track).1)
+            }
+            Problem::OmittedSegment { track, suffix } => {
+                write!(f, "{track}: segment {}{suffix} left out", split_track_key(track).1)
+            }
+            Problem::AmbiguousTrack { track, files } => write!(
+                f,
+                "{track}: track found in more than one SBF ({}), using {}; qualify it as {}",
+                files.join(", "),
+                files[0],
+                qualified_key(&files[0], track)
+            ),
+            Problem::UnconfiguredTrack { file, track } => write!(f, "{track}: track in {file} is not in the config"),
+            Problem::DuplicateTrack { file, track, keys } => {
+                write!(f, "{track}: track in {file} is configured more than once ({})", keys.join(", "))
+            }
+        }
+    }
+}
+
+pub fn validate(config: &ArrangementConfig, sbfs: &[SBF]) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut configured = BTreeMap::<(&str, &str), Vec<String>>::new();
+    for track in config.tracks.keys() {
+        let resolved = match config.resolve(track, sbfs) {
+            Resolution::Found(resolved) => resolved,
+            Resolution::UnknownFile { file } => {
+                problems.push(Problem::UnknownFile { track: track.to_owned(), file: file.to_owned() });
+                continue;
+            }
+            Resolution::MissingTrack { .. } => {
+                problems.push(Problem::MissingTrack { track: track.to_owned() });
+                continue;
+            }
+        };
+        let sbf = resolved.sbf;
+        let ident = resolved.ident;
+        configured.entry((&sbf.name, ident)).or_default().push(track.to_owned());
+        if !resolved.also_in.is_empty() {
+            problems.push(Problem::AmbiguousTrack {
+                track: track.to_owned(),
+                files: std::iter::once(sbf).chain(resolved.also_in).map(|sbf| sbf.name.to_owned()).collect(),
+            });
+        }
+
+        let mut counts = BTreeMap::<&str, usize>::new();
+        for suffix in &resolved.arrangement.order {
+            *counts.entry(suffix).or_default() += 1;
+        }
+        for (suffix, count) in &counts {
+            if sbf.segment(ident, suffix).is_none() {
+                problems.push(Problem::UnknownSuffix { track: track.to_owned(), suffix: suffix.to_string() });
+            } else if *count > 1 {
+                problems.push(Problem::DuplicateSuffix { track: track.to_owned(), suffix: suffix.to_string(), count: *count });
+            }
+        }
+        for e in &sbf.grouped_chunks[ident] {
+            if !counts.contains_key(e.suffix.as_str()) {
+                problems.push(Problem::OmittedSegment { track: track.to_owned(), suffix: e.suffix.to_owned() });
+            }
+        }
+    }
+    for ((file, track), keys) in &configured {
+        if keys.len() > 1 {
+            problems.push(Problem::DuplicateTrack { file: file.to_string(), track: track.to_string(), keys: keys.clone() });
+        }
+    }
+    for sbf in sbfs {
+        let mut tracks = sbf.grouped_chunks.keys().collect::<Vec<_>>();
+        tracks.sort();
+        for track in tracks {
+            if !configured.contains_key(&(sbf.name.as_str(), track.as_str())) {
+                problems.push(Problem::UnconfiguredTrack { file: sbf.name.to_owned(), track: track.to_owned() });
+            }
+        }
+    }
+    problems
+}
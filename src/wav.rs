@@ -0,0 +1,287 @@
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use anyhow::anyhow;
+
+use crate::encoder::SampleFormat;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// tail of the KSDATAFORMAT_SUBTYPE_* GUIDs, the format code goes in front
+const SUBTYPE_GUID_TAIL: [u8; 12] = [0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+}
+
+impl WavFormat {
+    pub fn bytes_per_sample(&self) -> u16 {
+        self.sample_format.bits() / 8
+    }
+
+    pub fn block_align(&self) -> u16 {
+        self.channels * self.bytes_per_sample()
+    }
+
+    pub fn data_size(&self, frames: u64) -> u64 {
+        frames * self.block_align() as u64
+    }
+
+    // 16-bit mono/stereo stays plain PCM for old readers, everything else is WAVE_FORMAT_EXTENSIBLE
+    fn extensible(&self) -> bool {
+        self.sample_format != SampleFormat::Int16 || self.channels > 2
+    }
+
+    fn format_code(&self) -> u16 {
+        match self.sample_format {
+            SampleFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+
+    fn channel_mask(&self) -> u32 {
+        match self.channels {
+            // front center
+            1 => 0x4,
+            // front left, front right
+            2 => 0x3,
+            n => (1u32 << n.min(18)) - 1,
+        }
+    }
+
+    fn fmt_chunk(&self) -> Vec<u8> {
+        let mut body = vec![];
+        let tag = if self.extensible() { WAVE_FORMAT_EXTENSIBLE } else { self.format_code() };
+        body.extend(tag.to_le_bytes());
+        body.extend(self.channels.to_le_bytes());
+        body.extend(self.sample_rate.to_le_bytes());
+        body.extend((self.sample_rate * self.block_align() as u32).to_le_bytes());
+        body.extend(self.block_align().to_le_bytes());
+        body.extend(self.sample_format.bits().to_le_bytes());
+        if self.extensible() {
+            body.extend(22u16.to_le_bytes());
+            // valid bits
+            body.extend(self.sample_format.bits().to_le_bytes());
+            body.extend(self.channel_mask().to_le_bytes());
+            body.extend((self.format_code() as u32).to_le_bytes());
+            body.extend(SUBTYPE_GUID_TAIL);
+        }
+        let mut chunk = b"fmt ".to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        chunk
+    }
+}
+
+/// Everything in front of the sample data of a WAV file holding `frames` frames, with `chunks`
+/// (complete RIFF chunks, metadata etc.) between `fmt ` and `data`. Files whose RIFF size doesn't
+/// fit 32 bits are written as RF64 with the sizes in a `ds64` chunk; `reserve_ds64` puts a `JUNK`
+/// chunk of the same size there otherwise, so the header length doesn't depend on `frames`. Odd
+/// sized data needs a pad byte after it, see `data_padding`.
+pub fn header(format: &WavFormat, frames: u64, chunks: &[u8], reserve_ds64: bool) -> Vec<u8> {
+    let data_size = format.data_size(frames);
+    let fmt = format.fmt_chunk();
+    // float isn't PCM, which requires a `fact` chunk with the number of frames
+    let fact_size = if format.sample_format == SampleFormat::Float32 { 12 } else { 0 };
+    let body_size = 4 + fmt.len() as u64 + fact_size + chunks.len() as u64 + 8 + data_size + data_padding(format, frames) as u64;
+    let rf64 = body_size + if reserve_ds64 { 36 } else { 0 } > u32::MAX as u64;
+    let riff_size = if rf64 || reserve_ds64 { body_size + 36 } else { body_size };
+    let clamp = |size: u64| if rf64 { u32::MAX } else { size as u32 };
+
+    let mut out = vec![];
+    out.extend(if rf64 { b"RF64" } else { b"RIFF" });
+    out.extend(clamp(riff_size).to_le_bytes());
+    out.extend(b"WAVE");
+    if rf64 {
+        out.extend(b"ds64");
+        out.extend(28u32.to_le_bytes());
+        out.extend(riff_size.to_le_bytes());
+        out.extend(data_size.to_le_bytes());
+        out.extend(frames.to_le_bytes());
+        // no table entries
+        out.extend(0u32.to_le_bytes());
+    } else if reserve_ds64 {
+        out.extend(b"JUNK");
+        out.extend(28u32.to_le_bytes());
+        out.extend([0; 28]);
+    }
+    out.extend(fmt);
+    if fact_size > 0 {
+        out.extend(b"fact");
+        out.extend(4u32.to_le_bytes());
+        out.extend(clamp(frames).to_le_bytes());
+    }
+    out.extend(chunks);
+    out.extend(b"data");
+    out.extend(clamp(data_size).to_le_bytes());
+    out
+}
+
+/// The format and length of a WAV file as written by `header`, from its first bytes.
+pub fn read_header(data: &[u8]) -> anyhow::Result<(WavFormat, u64)> {
+    let u16_at = |at: usize| -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(data.get(at..at + 2).ok_or(anyhow!("truncated header"))?.try_into().unwrap()))
+    };
+    let u32_at = |at: usize| -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(data.get(at..at + 4).ok_or(anyhow!("truncated header"))?.try_into().unwrap()))
+    };
+    if data.len() < 12 || !(data.starts_with(b"RIFF") || data.starts_with(b"RF64")) || &data[8..12] != b"WAVE" {
+        return Err(anyhow!("not a WAV file"));
+    }
+    let mut format = None;
+    let mut ds64_data_size = None;
+    let mut at = 12;
+    loop {
+        let id = data.get(at..at + 4).ok_or(anyhow!("no data chunk"))?;
+        let size = u32_at(at + 4)?;
+        let body = at + 8;
+        match id {
+            b"ds64" => ds64_data_size = Some(u32_at(body + 8)? as u64 | (u32_at(body + 12)? as u64) << 32),
+            b"fmt " => {
+                let tag = u16_at(body)?;
+                let code = if tag == WAVE_FORMAT_EXTENSIBLE { u16_at(body + 24)? } else { tag };
+                let sample_format = match (code, u16_at(body + 14)?) {
+                    (WAVE_FORMAT_PCM, 16) => SampleFormat::Int16,
+                    (WAVE_FORMAT_PCM, 24) => SampleFormat::Int24,
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Float32,
+                    (code, bits) => return Err(anyhow!("unsupported format {code} with {bits} bits")),
+                };
+                format = Some(WavFormat {
+                    channels: u16_at(body + 2)?,
+                    sample_rate: u32_at(body + 4)?,
+                    sample_format,
+                });
+            }
+            b"data" => {
+                let format = format.ok_or(anyhow!("data before fmt chunk"))?;
+                let data_size = match ds64_data_size {
+                    Some(data_size) if size == u32::MAX => data_size,
+                    _ => size as u64,
+                };
+                return Ok((format, data_size / format.block_align() as u64));
+            }
+            _ => {}
+        }
+        at = body + size as usize + size as usize % 2;
+    }
+}
+
+pub fn data_padding(format: &WavFormat, frames: u64) -> usize {
+    (format.data_size(frames) % 2) as usize
+}
+
+/// Writes samples in the file's format.
+pub fn write_i16(writer: &mut impl Write, format: SampleFormat, samples: &[i16]) -> std::io::Result<()> {
+    for &s in samples {
+        match format {
+            SampleFormat::Int16 => writer.write_all(&s.to_le_bytes())?,
+            SampleFormat::Int24 => writer.write_all(&((s as i32) << 8).to_le_bytes()[0..3])?,
+            SampleFormat::Float32 => writer.write_all(&(s as f32 / 32768.0).to_le_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+pub fn write_f32(writer: &mut impl Write, format: SampleFormat, samples: &[f32]) -> std::io::Result<()> {
+    for &s in samples {
+        match format {
+            SampleFormat::Int16 => writer.write_all(&(format.quantize(s) as i16).to_le_bytes())?,
+            SampleFormat::Int24 => writer.write_all(&format.quantize(s).to_le_bytes()[0..3])?,
+            SampleFormat::Float32 => writer.write_all(&s.to_le_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Buffered WAV writer. With the length known upfront (`new`) the header is final and the output
+/// needn't be seekable, so it works for pipes; `streaming` writes a placeholder header that
+/// `finalize` patches with the real sizes.
+pub struct WavWriter<W: Write> {
+    out: BufWriter<W>,
+    format: WavFormat,
+    chunks: Vec<u8>,
+    // frames promised in the header, `None` when it gets patched
+    declared: Option<u64>,
+    samples: u64,
+}
+
+impl<W: Write> WavWriter<W> {
+    pub fn new(out: W, format: WavFormat, frames: u64, chunks: Vec<u8>) -> anyhow::Result<Self> {
+        let mut out = BufWriter::with_capacity(BUFFER_SIZE, out);
+        out.write_all(&header(&format, frames, &chunks, false))?;
+        Ok(Self { out, format, chunks, declared: Some(frames), samples: 0 })
+    }
+
+    fn take(&mut self, samples: usize) -> anyhow::Result<()> {
+        self.samples += samples as u64;
+        match self.declared {
+            Some(frames) if self.samples > frames * self.format.channels as u64 => {
+                Err(anyhow!("more samples written than declared in the WAV header"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn write_i16(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.take(samples.len())?;
+        write_i16(&mut self.out, self.format.sample_format, samples)?;
+        Ok(())
+    }
+
+    pub fn write_f32(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.take(samples.len())?;
+        write_f32(&mut self.out, self.format.sample_format, samples)?;
+        Ok(())
+    }
+
+    fn frames(&self) -> u64 {
+        self.samples / self.format.channels as u64
+    }
+
+    // pads the data chunk and checks the length against the header
+    fn end_data(&mut self) -> anyhow::Result<()> {
+        if let Some(frames) = self.declared {
+            if frames != self.frames() {
+                return Err(anyhow!("{} frames short of the length declared in the WAV header", frames - self.frames()));
+            }
+        }
+        self.out.write_all(&vec![0; data_padding(&self.format, self.frames())])?;
+        Ok(())
+    }
+
+    /// Finishes a writer created with `new`.
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        if self.declared.is_none() {
+            return Err(anyhow!("a streaming WAV writer has to be finalized"));
+        }
+        self.end_data()?;
+        Ok(self.out.into_inner().map_err(|e| e.into_error())?)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn streaming(out: W, format: WavFormat, chunks: Vec<u8>) -> anyhow::Result<Self> {
+        let mut out = BufWriter::with_capacity(BUFFER_SIZE, out);
+        out.write_all(&header(&format, 0, &chunks, true))?;
+        Ok(Self { out, format, chunks, declared: None, samples: 0 })
+    }
+
+    /// Finishes either kind of writer, patching the header of a streaming one.
+    pub fn finalize(mut self) -> anyhow::Result<W> {
+        if self.declared.is_some() {
+            return self.finish();
+        }
+        self.end_data()?;
+        let header = header(&self.format, self.frames(), &self.chunks, true);
+        let mut out = self.out.into_inner().map_err(|e| e.into_error())?;
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&header)?;
+        out.seek(SeekFrom::End(0))?;
+        Ok(out)
+    }
+}
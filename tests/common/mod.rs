@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+
+// an SBF holding `segments` (ident, blocks, level), every block full (2048 stereo frames) but the
+// last (1000 frames)
+pub fn write_sbf(path: &Path, segments: &[(&str, usize, u8)]) {
+    let mut data = vec![];
+    let mut index = vec![];
+    for &(ident, blocks, level) in segments {
+        let start = 24 + data.len() as u32;
+        for block in 0..blocks {
+            let size = if block + 1 < blocks { 4096 } else { 2000 };
+            data.extend((size as u32).to_le_bytes());
+            data.extend([1, 0, 250, 0]);
+            data.extend((0..4096).map(|i| if i < size { level.wrapping_add((i % 7) as u8) } else { 0 }));
+        }
+        let mut name = [0; 8];
+        name[..ident.len()].copy_from_slice(ident.as_bytes());
+        index.extend(name);
+        for field in [0, 0, start, data.len() as u32 + 24 - start, 4104, 0] {
+            index.extend(field.to_le_bytes());
+        }
+    }
+    let mut out = b"SBF1".to_vec();
+    for field in [1, 2, 3, 24 + data.len() as u32, segments.len() as u32] {
+        out.extend(field.to_le_bytes());
+    }
+    out.extend(data);
+    out.extend(index);
+    std::fs::write(path, out).unwrap();
+}
+
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dfbhd-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("game")).unwrap();
+    dir
+}
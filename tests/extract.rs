@@ -0,0 +1,138 @@
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{scratch_dir, write_sbf};
+use dfbhd_mus::wav::read_header;
+
+fn frames(path: &Path) -> u64 {
+    read_header(&std::fs::read(path).unwrap()).unwrap().1
+}
+
+#[test]
+fn prefix_in_two_files() {
+    let dir = scratch_dir("extract");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 2, 100), ("m3a010b", 1, 110), ("m3a020a", 1, 120)]);
+    write_sbf(&dir.join("game/EXP1.sbf"), &[("m3a010a", 1, 200), ("SHAT01", 1, 210)]);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dfbhd"))
+        .current_dir(&dir)
+        .args(["extract", "-g", "game", "-o", "out", "--group-by-prefix", "--ident-names", "--segments", "also"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // each SBF gets its own track and segments, named after the file
+    let wav = dir.join("out/wav");
+    assert!(!wav.join("m3.wav").exists());
+    assert_eq!(frames(&wav.join("gamemus_m3.wav")), 2048 + 1000 + 1000 + 1000);
+    assert_eq!(frames(&wav.join("EXP1_m3.wav")), 1000);
+    assert_eq!(frames(&wav.join("gamemus_m3/a010a.wav")), 2048 + 1000);
+    assert_eq!(frames(&wav.join("EXP1_m3/a010a.wav")), 1000);
+    assert!(wav.join("gamemus_m3/a020a.wav").exists());
+    assert!(!wav.join("EXP1_m3/a020a.wav").exists());
+    // tracks only in one SBF keep their ident
+    assert!(wav.join("SHAT.wav").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tier_beside_whole_track() {
+    let dir = scratch_dir("tier");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 2, 100), ("m3a010b", 1, 110), ("m3a020a", 1, 120)]);
+    let extract = |extra: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_dfbhd"))
+            .current_dir(&dir)
+            .args(["extract", "-g", "game", "-o", "out", "--group-by-prefix", "--ident-names", "--segments", "also"])
+            .args(extra)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    extract(&[]);
+    extract(&["--tier", "a020"]);
+
+    // the tier gets its own name, the whole track and its segments stay
+    let wav = dir.join("out/wav");
+    assert_eq!(frames(&wav.join("m3.wav")), 2048 + 1000 + 1000 + 1000);
+    assert_eq!(frames(&wav.join("m3.a020.wav")), 1000);
+    assert!(wav.join("m3/a010a.wav").exists());
+    assert!(wav.join("m3.a020/a020a.wav").exists());
+    assert!(!wav.join("m3.a020/a010a.wav").exists());
+    let segments = std::fs::read_to_string(wav.join("m3/segments.json")).unwrap();
+    assert!(segments.contains("a010a") && segments.contains("a020a"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn broken_blocks_fail_their_file() {
+    let dir = scratch_dir("broken");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 1, 100)]);
+    // a block claiming more than 4096 bytes, and one with a scale that doesn't fit 16 bits
+    write_sbf(&dir.join("game/size.dat"), &[("CRED01", 1, 100), ("CRED02", 1, 100)]);
+    let mut data = std::fs::read(dir.join("game/size.dat")).unwrap();
+    data[24 + 4104..24 + 4104 + 4].copy_from_slice(&5000u32.to_le_bytes());
+    std::fs::write(dir.join("game/size.dat"), data).unwrap();
+    write_sbf(&dir.join("game/scale.dat"), &[("SHAT01", 1, 100)]);
+    let mut data = std::fs::read(dir.join("game/scale.dat")).unwrap();
+    data[24 + 4] = 16;
+    std::fs::write(dir.join("game/scale.dat"), data).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dfbhd"))
+        .current_dir(&dir)
+        .args(["extract", "-g", "game", "-o", "out", "--group-by-prefix", "--ident-names", "--include", "*.dat"])
+        .status()
+        .unwrap();
+    // reported as an error instead of a panic, and the good file is still exported
+    assert_eq!(status.code(), Some(1));
+    assert_eq!(frames(&dir.join("out/wav/m3.wav")), 1000);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_glob_matching_nothing() {
+    let dir = scratch_dir("glob");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 1, 100)]);
+
+    // a track name given as a file glob doesn't quietly export everything
+    let status = Command::new(env!("CARGO_BIN_EXE_dfbhd"))
+        .current_dir(&dir)
+        .args(["extract", "-g", "game", "-o", "out", "--group-by-prefix", "--exclude", "m3"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+    assert!(!dir.join("out").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn changed_output_is_exported_again() {
+    let dir = scratch_dir("changed");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 1, 100)]);
+    let extract = || {
+        let status = Command::new(env!("CARGO_BIN_EXE_dfbhd"))
+            .current_dir(&dir)
+            .args(["extract", "-g", "game", "-o", "out", "--group-by-prefix", "--ident-names"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    extract();
+    let track = dir.join("out/wav/m3.wav");
+    let exported = std::fs::read(&track).unwrap();
+
+    // same build key, but not the file the manifest describes
+    let mut edited = exported.clone();
+    *edited.last_mut().unwrap() ^= 1;
+    std::fs::write(&track, edited).unwrap();
+    extract();
+    assert_eq!(std::fs::read(&track).unwrap(), exported);
+    assert!(!dir.join("out/.dfbhd-staging").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,83 @@
+use std::io::Cursor;
+
+use dfbhd_mus::flac::{read_streaminfo, FlacEncoder};
+use dfbhd_mus::hash::Md5;
+
+// a sine with some noise on top, different per channel, interleaved
+fn signal(channels: usize, frames: usize, bits: u32) -> Vec<i32> {
+    let amplitude = (1 << (bits - 2)) as f64;
+    let mut seed = 0x2545f491u32;
+    let mut noise = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        (seed % 512) as f64 - 256.0
+    };
+    (0..frames)
+        .flat_map(|n| (0..channels).map(move |c| (n, c)))
+        .map(|(n, c)| (amplitude * (n as f64 * 0.01 * (c + 1) as f64).sin() + noise()) as i32)
+        .collect()
+}
+
+fn encode(samples: &[i32], channels: u16, bits: u32, piece: usize) -> Vec<u8> {
+    let comments = [("TITLE".to_owned(), "test".to_owned())];
+    let mut encoder = FlacEncoder::new(Cursor::new(vec![]), channels, bits, 22050, &comments).unwrap();
+    // uneven pieces so partial blocks get buffered
+    for piece in samples.chunks(piece * channels as usize) {
+        encoder.write_samples(piece).unwrap();
+    }
+    encoder.finish().unwrap().into_inner()
+}
+
+fn round_trip(channels: u16, bits: u32, frames: usize) {
+    let samples = signal(channels as usize, frames, bits);
+    let flac = encode(&samples, channels, bits, 1000);
+
+    let mut reader = claxon::FlacReader::new(Cursor::new(&flac)).unwrap();
+    let info = reader.streaminfo();
+    assert_eq!(info.channels, channels as u32);
+    assert_eq!(info.bits_per_sample, bits);
+    assert_eq!(info.sample_rate, 22050);
+    assert_eq!(info.samples, Some(frames as u64));
+    let mut md5 = Md5::default();
+    for s in &samples {
+        md5.update(&s.to_le_bytes()[..bits.div_ceil(8) as usize]);
+    }
+    assert_eq!(info.md5sum, md5.finish(), "STREAMINFO MD5 of {channels} channels, {bits} bits, {frames} frames");
+    let decoded = reader.samples().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(decoded, samples, "samples of {channels} channels, {bits} bits, {frames} frames");
+
+    let header = read_streaminfo(&flac).unwrap();
+    assert_eq!((header.channels, header.bits, header.sample_rate, header.frames), (channels, bits as u16, 22050, frames as u64));
+}
+
+#[test]
+fn mono() {
+    round_trip(1, 16, 4096 * 3);
+    round_trip(1, 16, 4096 * 2 + 123);
+}
+
+#[test]
+fn stereo() {
+    round_trip(2, 16, 4096 * 3);
+    round_trip(2, 16, 4096 * 2 + 4095);
+    round_trip(2, 24, 10_001);
+}
+
+#[test]
+fn short() {
+    // shorter than one block, down to a single frame
+    for frames in [1, 2, 5, 31, 4095] {
+        round_trip(1, 16, frames);
+        round_trip(2, 16, frames);
+    }
+}
+
+#[test]
+fn silence_and_full_scale() {
+    let samples = (0..9000).map(|n| if n < 4500 { 0 } else if n % 2 == 0 { i16::MAX as i32 } else { i16::MIN as i32 }).collect::<Vec<_>>();
+    let flac = encode(&samples, 2, 16, 777);
+    let mut reader = claxon::FlacReader::new(Cursor::new(&flac)).unwrap();
+    let decoded = reader.samples().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(decoded, samples);
+}
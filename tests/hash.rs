@@ -0,0 +1,58 @@
+use dfbhd_mus::hash::{to_hex, Md5, Sha256};
+
+// (input, MD5, SHA-256), from RFC 1321 and FIPS 180-2
+fn vectors() -> Vec<(Vec<u8>, &'static str, &'static str)> {
+    vec![
+        (b"".to_vec(), "d41d8cd98f00b204e9800998ecf8427e", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        (b"abc".to_vec(), "900150983cd24fb0d6963f7d28e17f72", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        // 80 bytes, more than one block
+        (
+            b"1234567890".repeat(8),
+            "57edf4a22be3c955ac49da2e2107b67a",
+            "f371bc4a311f2b009eef952dd83ca80e2b60026c8e935592d0f9c308453c813e",
+        ),
+        // 56 bytes, the length no longer fits the padded block
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".to_vec(),
+            "8215ef0796a20bcaaae116d3876c664a",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            vec![b'a'; 1_000_000],
+            "7707d6ae4e027c70eea2a935c2296f21",
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+        ),
+    ]
+}
+
+fn md5(pieces: std::slice::Chunks<u8>) -> String {
+    let mut md5 = Md5::default();
+    pieces.for_each(|piece| md5.update(piece));
+    to_hex(&md5.finish())
+}
+
+fn sha256(pieces: std::slice::Chunks<u8>) -> String {
+    let mut sha256 = Sha256::default();
+    pieces.for_each(|piece| sha256.update(piece));
+    to_hex(&sha256.finish())
+}
+
+#[test]
+fn known_answers() {
+    for (input, expected_md5, expected_sha256) in vectors() {
+        let whole = input.len().max(1);
+        assert_eq!(md5(input.chunks(whole)), expected_md5, "MD5 of {} bytes", input.len());
+        assert_eq!(sha256(input.chunks(whole)), expected_sha256, "SHA-256 of {} bytes", input.len());
+    }
+}
+
+#[test]
+fn split_updates() {
+    // pieces that straddle block boundaries give the same digest as one update
+    for (input, expected_md5, expected_sha256) in vectors() {
+        for piece in [1, 7, 63, 65, 1000] {
+            assert_eq!(md5(input.chunks(piece)), expected_md5, "MD5 of {} bytes in {piece} byte pieces", input.len());
+            assert_eq!(sha256(input.chunks(piece)), expected_sha256, "SHA-256 of {} bytes in {piece} byte pieces", input.len());
+        }
+    }
+}
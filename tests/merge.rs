@@ -0,0 +1,102 @@
+use dfbhd_mus::arrangement::ArrangementConfig;
+use dfbhd_mus::merge::{diff, merge, order_changes, OrderChange, TrackDiff};
+
+fn config(json: &str) -> ArrangementConfig {
+    ArrangementConfig::from_json(json).unwrap()
+}
+
+fn order(suffixes: &str) -> Vec<String> {
+    suffixes.split_whitespace().map(|s| s.to_owned()).collect()
+}
+
+#[test]
+fn order_changes_outside_common_subsequence() {
+    assert_eq!(order_changes(&order("a b c"), &order("a b c")), vec![]);
+    assert_eq!(
+        order_changes(&order("a b c d"), &order("a c d b")),
+        vec![OrderChange::Moved { suffix: "b".into(), from: 1, to: 3 }]
+    );
+    assert_eq!(
+        order_changes(&order("a b c"), &order("a x c")),
+        vec![
+            OrderChange::Added { suffix: "x".into(), at: 1 },
+            OrderChange::Removed { suffix: "b".into(), from: 1 },
+        ]
+    );
+    // a second copy of a suffix is added, not moved
+    assert_eq!(
+        order_changes(&order("a b"), &order("b a b")),
+        vec![OrderChange::Added { suffix: "b".into(), at: 0 }]
+    );
+}
+
+#[test]
+fn diff_tracks() {
+    let old = config(r#"{"m3": ["a", "b"], "m4": ["a"], "m5": ["a"]}"#);
+    let new = config(r#"{"m3": {"order": ["b", "a"], "crossfade_ms": 20}, "m4": ["a"], "m6": ["a", "b", "c"]}"#);
+    assert_eq!(
+        diff(&old, &new),
+        vec![
+            TrackDiff::Changed {
+                key: "m3".into(),
+                order: vec![OrderChange::Moved { suffix: "a".into(), from: 0, to: 1 }],
+                joins_changed: true,
+            },
+            TrackDiff::Removed { key: "m5".into() },
+            TrackDiff::Added { key: "m6".into(), segments: 3 },
+        ]
+    );
+}
+
+#[test]
+fn clean_merge() {
+    let base = config(r#"{"m3": ["a", "b", "c"], "m4": ["a"], "m5": ["a"]}"#);
+    // ours reorders m3 and removes m5, theirs changes the crossfade of m3 and adds m6
+    let ours = config(r#"{"m3": ["c", "b", "a"], "m4": ["a"]}"#);
+    let theirs = config(r#"{"m3": {"order": ["a", "b", "c"], "crossfade_ms": 50}, "m4": ["a"], "m5": ["a"], "m6": ["b"]}"#);
+    let merged = merge(&base, &ours, &theirs);
+    assert!(merged.conflicts.is_empty(), "{:?}", merged.conflicts);
+    assert_eq!(
+        merged.config,
+        config(r#"{"m3": {"order": ["c", "b", "a"], "crossfade_ms": 50}, "m4": ["a"], "m6": ["b"]}"#)
+    );
+}
+
+#[test]
+fn conflicting_merge_keeps_ours() {
+    let base = config(r#"{"$defaults": {"crossfade_ms": 10}, "m3": {"order": ["a", "b"], "joins": {"b": {"declick_ms": 1}}}}"#);
+    let ours = config(r#"{"$defaults": {"crossfade_ms": 20}, "m3": {"order": ["b", "a"], "joins": {"b": {"declick_ms": 2}}}}"#);
+    let theirs = config(r#"{"$defaults": {"crossfade_ms": 30}, "m3": {"order": ["a", "b", "c"], "joins": {"b": {"declick_ms": 3}}}}"#);
+    let merged = merge(&base, &ours, &theirs);
+    let conflicts = merged.conflicts.iter().map(|c| (c.key.as_str(), c.what)).collect::<Vec<_>>();
+    assert_eq!(
+        conflicts,
+        [("$defaults", "join settings"), ("m3", "segment order"), ("m3 join b", "join settings")]
+    );
+    assert_eq!(merged.config, ours);
+}
+
+#[test]
+fn delete_vs_edit() {
+    let base = config(r#"{"m3": ["a", "b"], "m4": ["a", "b"]}"#);
+    // ours edits m3 and removes m4, theirs does the opposite
+    let ours = config(r#"{"m3": ["b", "a"]}"#);
+    let theirs = config(r#"{"m4": ["b", "a"]}"#);
+    let merged = merge(&base, &ours, &theirs);
+    let conflicts = merged.conflicts.iter().map(|c| (c.key.as_str(), c.what)).collect::<Vec<_>>();
+    assert_eq!(conflicts, [("m3", "track"), ("m4", "track")]);
+    // ours wins both ways: the edit is kept, the removal too
+    assert_eq!(merged.config, ours);
+}
+
+#[test]
+fn added_on_both_sides() {
+    let base = config("{}");
+    let merged = merge(&base, &config(r#"{"m3": ["a"]}"#), &config(r#"{"m3": ["a"]}"#));
+    assert!(merged.conflicts.is_empty());
+    assert_eq!(merged.config, config(r#"{"m3": ["a"]}"#));
+
+    let merged = merge(&base, &config(r#"{"m3": ["a"]}"#), &config(r#"{"m3": ["b"]}"#));
+    assert_eq!(merged.conflicts.len(), 1);
+    assert_eq!(merged.config, config(r#"{"m3": ["a"]}"#));
+}
@@ -0,0 +1,65 @@
+use dfbhd_mus::mix::{join, layout, Curve, JoinSettings};
+
+fn joins(crossfade_ms: u32, declick_ms: u32) -> JoinSettings {
+    JoinSettings { crossfade_ms, curve: Curve::EqualPower, declick_ms }
+}
+
+// stereo segments of the given lengths in frames
+fn segments(lengths: &[usize]) -> Vec<Vec<i16>> {
+    lengths.iter().enumerate().map(|(idx, &frames)| vec![1000 * idx as i16 + 1; frames * 2]).collect()
+}
+
+#[test]
+fn layout_matches_join() {
+    let cases: [(&[usize], Vec<JoinSettings>); 5] = [
+        (&[], vec![]),
+        (&[500], vec![]),
+        (&[1000, 2000, 300], vec![joins(0, 0), joins(0, 5)]),
+        // 10 ms is 220 frames, clamped to the shorter side of each join
+        (&[1000, 100, 2000, 50], vec![joins(10, 0), joins(10, 0), joins(10, 0)]),
+        (&[300, 300, 300], vec![joins(1000, 0), joins(5, 2)]),
+    ];
+    for (lengths, settings) in cases {
+        let joined = join(segments(lengths), &settings);
+        let (offsets, frames) = layout(lengths, &settings);
+        assert_eq!(offsets, joined.offsets, "offsets of {lengths:?}");
+        assert_eq!(frames, joined.frames(), "length of {lengths:?}");
+    }
+}
+
+#[test]
+fn crossfade_shortens_by_clamped_fade() {
+    // 10 ms is 220 frames
+    let joined = join(segments(&[1000, 2000]), &[joins(10, 0)]);
+    assert_eq!(joined.offsets, [0, 780]);
+    assert_eq!(joined.frames(), 2780);
+    // clamped to the next segment, then to what is left of the previous one
+    let joined = join(segments(&[1000, 100, 2000]), &[joins(10, 0), joins(10, 0)]);
+    assert_eq!(joined.offsets, [0, 900, 900]);
+    assert_eq!(joined.frames(), 2900);
+    assert_eq!(joined.lengths, [1000, 100, 2000]);
+    // a crossfade replaces the declick
+    let joined = join(segments(&[1000, 2000]), &[joins(10, 5)]);
+    assert_eq!(joined.offsets, [0, 780]);
+    assert_eq!(joined.frames(), 2780);
+}
+
+#[test]
+fn declick_keeps_length() {
+    // 5 ms is 110 frames on each side of the join
+    let joined = join(segments(&[1000, 2000]), &[joins(0, 5)]);
+    assert_eq!(joined.offsets, [0, 1000]);
+    assert_eq!(joined.frames(), 3000);
+    let left = |frame: usize| joined.samples[frame * 2];
+    assert_eq!(left(889), 1);
+    assert_eq!(left(999), 0);
+    assert!(left(1000) < 10);
+    assert_eq!(left(1110), 1001);
+
+    // clamped to the shorter side
+    let joined = join(segments(&[1000, 50]), &[joins(0, 5)]);
+    assert_eq!(joined.offsets, [0, 1000]);
+    assert_eq!(joined.frames(), 1050);
+    assert_eq!(joined.samples[949 * 2], 1);
+    assert!(joined.samples[1049 * 2] < 1001);
+}
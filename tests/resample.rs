@@ -0,0 +1,114 @@
+use std::f64::consts::PI;
+
+use dfbhd_mus::resample::{Quality, Resampler};
+
+fn resample(signal: &[f64], from: u32, to: u32, quality: Quality) -> Vec<f64> {
+    let mut resampler = Resampler::new(1, from, to, quality);
+    let mut out = vec![];
+    // uneven pieces so the streaming state gets exercised
+    for piece in signal.chunks(1000) {
+        resampler.process_f64(piece, &mut out);
+    }
+    resampler.finish_f64(&mut out);
+    assert_eq!(out.len() as u64, resampler.output_frames(signal.len() as u64));
+    out
+}
+
+fn sine(freq: f64, rate: u32, frames: usize) -> Vec<f64> {
+    (0..frames).map(|n| (2.0 * PI * freq * n as f64 / rate as f64).sin()).collect()
+}
+
+// least squares amplitude of `freq` and the rms of what's left, over the middle of `signal`
+fn analyze(signal: &[f64], freq: f64, rate: u32) -> (f64, f64) {
+    let part = &signal[signal.len() / 4..signal.len() * 3 / 4];
+    let offset = signal.len() / 4;
+    let w = |n: usize| 2.0 * PI * freq * (n + offset) as f64 / rate as f64;
+    let (mut ss, mut sc, mut cc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (n, &y) in part.iter().enumerate() {
+        let (s, c) = w(n).sin_cos();
+        ss += s * s;
+        sc += s * c;
+        cc += c * c;
+        ys += y * s;
+        yc += y * c;
+    }
+    let det = ss * cc - sc * sc;
+    let a = (ys * cc - yc * sc) / det;
+    let b = (yc * ss - ys * sc) / det;
+    let residual = part
+        .iter()
+        .enumerate()
+        .map(|(n, &y)| {
+            let (s, c) = w(n).sin_cos();
+            (y - a * s - b * c).powi(2)
+        })
+        .sum::<f64>();
+    ((a * a + b * b).sqrt(), (residual / part.len() as f64).sqrt())
+}
+
+fn db(x: f64) -> f64 {
+    20.0 * x.log10()
+}
+
+#[test]
+fn passband_is_flat() {
+    for to in [44100, 48000] {
+        for freq in [50.0, 440.0, 1000.0, 4000.0, 8000.0, 9500.0] {
+            let out = resample(&sine(freq, 22050, 22050), 22050, to, Quality::High);
+            let (amplitude, _) = analyze(&out, freq, to);
+            assert!(db(amplitude).abs() < 0.01, "{freq} Hz to {to} Hz: {:.4} dB", db(amplitude));
+        }
+    }
+}
+
+#[test]
+fn images_are_rejected_when_upsampling() {
+    for to in [44100, 48000] {
+        for freq in [1000.0, 6000.0, 9000.0] {
+            let out = resample(&sine(freq, 22050, 22050), 22050, to, Quality::High);
+            // anything that isn't the tone: images at 22050 - f, 22050 + f, ... and ripple
+            let (_, residual) = analyze(&out, freq, to);
+            let sine_rms = 0.5f64.sqrt();
+            assert!(db(residual / sine_rms) < -90.0, "{freq} Hz to {to} Hz: {:.1} dB", db(residual / sine_rms));
+        }
+    }
+}
+
+#[test]
+fn aliases_are_rejected_when_downsampling() {
+    // above the output Nyquist frequency, must not fold back
+    for freq in [12500.0, 15000.0, 20000.0] {
+        let out = resample(&sine(freq, 48000, 48000), 48000, 22050, Quality::High);
+        let part = &out[out.len() / 4..out.len() * 3 / 4];
+        let rms = (part.iter().map(|s| s * s).sum::<f64>() / part.len() as f64).sqrt();
+        assert!(db(rms / 0.5f64.sqrt()) < -90.0, "{freq} Hz: {:.1} dB", db(rms / 0.5f64.sqrt()));
+    }
+    let out = resample(&sine(1000.0, 48000, 48000), 48000, 22050, Quality::High);
+    assert!(db(analyze(&out, 1000.0, 22050).0).abs() < 0.01);
+}
+
+#[test]
+fn lower_qualities_still_work() {
+    for quality in [Quality::Fast, Quality::Standard] {
+        let out = resample(&sine(1000.0, 22050, 22050), 22050, 48000, quality);
+        let (amplitude, residual) = analyze(&out, 1000.0, 48000);
+        assert!(db(amplitude).abs() < 0.1);
+        assert!(db(residual) < -40.0);
+    }
+}
+
+#[test]
+fn stereo_channels_stay_separate() {
+    let left = sine(1000.0, 22050, 4410);
+    let interleaved = left.iter().flat_map(|&l| [l, 0.0]).collect::<Vec<_>>();
+    let mut resampler = Resampler::new(2, 22050, 44100, Quality::Standard);
+    let mut out = vec![];
+    resampler.process_f64(&interleaved, &mut out);
+    resampler.finish_f64(&mut out);
+    assert_eq!(out.len(), 4410 * 2 * 2);
+    assert!(out.iter().skip(1).step_by(2).all(|&r| r == 0.0));
+    // output frame 2n is input frame n
+    for n in 100..200 {
+        assert!((out[4 * n] - left[n]).abs() < 1e-3);
+    }
+}
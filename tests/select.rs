@@ -0,0 +1,73 @@
+use dfbhd_mus::arrangement::TrackArrangement;
+use dfbhd_mus::select::{TrackFilter, TrackPattern};
+
+fn patterns(patterns: &[&str]) -> Vec<TrackPattern> {
+    patterns.iter().map(|p| TrackPattern::parse(p).unwrap()).collect()
+}
+
+#[test]
+fn track_patterns() {
+    let matches = |pattern: &str, file: &str, ident: &str| TrackPattern::parse(pattern).unwrap().matches(file, ident);
+    // globs match the whole ident, ignoring case
+    assert!(matches("m*", "gamemus.sbf", "m3"));
+    assert!(matches("M3", "gamemus.sbf", "m3"));
+    assert!(!matches("m", "gamemus.sbf", "m3"));
+    // regexes match anywhere
+    assert!(matches("/HAT/", "gamemus.sbf", "SHAT"));
+    assert!(!matches("/^HAT/", "gamemus.sbf", "SHAT"));
+    // with a `:` the file is part of the name
+    assert!(matches("exp1.sbf:m*", "EXP1.sbf", "m3"));
+    assert!(!matches("exp1.sbf:m*", "gamemus.sbf", "m3"));
+    assert!(matches("/^EXP1.*:m/", "EXP1.sbf", "m3"));
+
+    assert!(TrackPattern::parse("").is_err());
+    assert!(TrackPattern::parse("/(/").is_err());
+}
+
+#[test]
+fn track_filter() {
+    assert!(TrackFilter::default().is_empty());
+    assert!(TrackFilter::default().selects_track("gamemus.sbf", "m3"));
+
+    let filter = TrackFilter {
+        tracks: patterns(&["m*", "SHAT"]),
+        exclude: patterns(&["EXP1.sbf:m3"]),
+        files: vec![glob::Pattern::new("*mus.sbf").unwrap(), glob::Pattern::new("exp1.sbf").unwrap()],
+        tiers: vec![],
+    };
+    assert!(!filter.is_empty());
+    assert!(filter.selects_track("gamemus.sbf", "m3"));
+    assert!(filter.selects_track("menumus.sbf", "SHAT"));
+    assert!(filter.selects_track("EXP1.sbf", "m4"));
+    // excluded in one file only
+    assert!(!filter.selects_track("EXP1.sbf", "m3"));
+    // not matching any track pattern, or in a file left out
+    assert!(!filter.selects_track("gamemus.sbf", "CRED"));
+    assert!(!filter.selects_track("other.dat", "m3"));
+}
+
+#[test]
+fn tier_filter() {
+    let filter = TrackFilter {
+        tiers: vec!["a020".into(), "B1".into()],
+        ..Default::default()
+    };
+    assert_eq!(filter.output_name("m3"), "m3.a020+B1");
+    assert_eq!(TrackFilter::default().output_name("m3"), "m3");
+    assert!(filter.selects_segment("a020a"));
+    assert!(filter.selects_segment("b10"));
+    assert!(!filter.selects_segment("a010a"));
+    assert!(!filter.selects_segment("a02"));
+
+    // the order keeps its arrangement, with the other tiers left out
+    let arrangement = TrackArrangement::new(["a020b", "a010a", "a020a"].map(String::from).to_vec());
+    let picked = filter.arrangement("gamemus.sbf", "m3", &arrangement).unwrap();
+    assert_eq!(picked.order, ["a020b", "a020a"]);
+    let other_tiers = TrackArrangement::new(vec!["a010a".into()]);
+    assert!(filter.arrangement("gamemus.sbf", "m3", &other_tiers).is_none());
+    let excluded = TrackFilter {
+        exclude: patterns(&["m3"]),
+        ..filter
+    };
+    assert!(excluded.arrangement("gamemus.sbf", "m3", &arrangement).is_none());
+}
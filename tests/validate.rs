@@ -0,0 +1,64 @@
+mod common;
+
+use common::{scratch_dir, write_sbf};
+use dfbhd_mus::arrangement::ArrangementConfig;
+use dfbhd_mus::sbf::SBF;
+use dfbhd_mus::validate::{validate, Problem};
+
+#[test]
+fn config_problems() {
+    let dir = scratch_dir("validate");
+    write_sbf(&dir.join("game/gamemus.sbf"), &[("m3a010a", 1, 100), ("m3a010b", 1, 110), ("m3a020a", 1, 120), ("SHAT01", 1, 130)]);
+    write_sbf(&dir.join("game/EXP1.sbf"), &[("m3a010a", 1, 200), ("CRED01", 1, 210)]);
+    let sbfs = ["gamemus.sbf", "EXP1.sbf"].map(|name| SBF::from_file(&dir.join("game").join(name)).unwrap());
+    let config = ArrangementConfig::from_json(
+        r#"{
+            "EXP1.sbf:m3": ["a010a"],
+            "gamemus.sbf:m3": ["a010a", "a010b", "a020a"],
+            "m3": ["a010b", "a010b", "a999"],
+            "m9": ["a"],
+            "nope.sbf:m3": ["a010a"]
+        }"#,
+    )
+    .unwrap();
+
+    let problems = validate(&config, &sbfs);
+    let s = |s: &str| s.to_owned();
+    assert_eq!(
+        problems,
+        [
+            Problem::AmbiguousTrack { track: s("m3"), files: vec![s("gamemus.sbf"), s("EXP1.sbf")] },
+            Problem::DuplicateSuffix { track: s("m3"), suffix: s("a010b"), count: 2 },
+            Problem::UnknownSuffix { track: s("m3"), suffix: s("a999") },
+            Problem::OmittedSegment { track: s("m3"), suffix: s("a010a") },
+            Problem::OmittedSegment { track: s("m3"), suffix: s("a020a") },
+            Problem::MissingTrack { track: s("m9") },
+            Problem::UnknownFile { track: s("nope.sbf:m3"), file: s("nope.sbf") },
+            Problem::DuplicateTrack {
+                file: s("gamemus.sbf"),
+                track: s("m3"),
+                keys: vec![s("gamemus.sbf:m3"), s("m3")],
+            },
+            Problem::UnconfiguredTrack { file: s("gamemus.sbf"), track: s("SHAT") },
+            Problem::UnconfiguredTrack { file: s("EXP1.sbf"), track: s("CRED") },
+        ]
+    );
+
+    // only the entries that can't be rendered as configured are errors
+    let errors = problems.iter().filter(|p| p.is_error()).map(|p| p.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        [
+            "m3: unknown segment m3a999",
+            "m9: track not found in any SBF",
+            "nope.sbf:m3: no SBF named nope.sbf",
+            "m3: track in gamemus.sbf is configured more than once (gamemus.sbf:m3, m3)",
+        ]
+    );
+    // which fail only their own entries
+    for (key, count) in [("EXP1.sbf:m3", 0), ("gamemus.sbf:m3", 1), ("m3", 6), ("m9", 1), ("SHAT", 0)] {
+        assert_eq!(problems.iter().filter(|p| p.concerns(key)).count(), count, "{key}");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,162 @@
+use std::io::Cursor;
+
+use dfbhd_mus::encoder::SampleFormat;
+use dfbhd_mus::wav::{header, read_header, WavFormat, WavWriter};
+
+fn format(channels: u16, sample_format: SampleFormat) -> WavFormat {
+    WavFormat { channels, sample_rate: 22050, sample_format }
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+#[test]
+fn pcm16_header() {
+    let out = header(&format(2, SampleFormat::Int16), 10, &[], false);
+    let mut expected = vec![];
+    expected.extend(b"RIFF");
+    expected.extend((36u32 + 40).to_le_bytes());
+    expected.extend(b"WAVE");
+    expected.extend(b"fmt ");
+    expected.extend(16u32.to_le_bytes());
+    expected.extend(1u16.to_le_bytes());
+    expected.extend(2u16.to_le_bytes());
+    expected.extend(22050u32.to_le_bytes());
+    expected.extend((22050u32 * 4).to_le_bytes());
+    expected.extend(4u16.to_le_bytes());
+    expected.extend(16u16.to_le_bytes());
+    expected.extend(b"data");
+    expected.extend(40u32.to_le_bytes());
+    assert_eq!(out, expected);
+    assert_eq!(read_header(&out).unwrap(), (format(2, SampleFormat::Int16), 10));
+}
+
+#[test]
+fn float_header() {
+    let out = header(&format(1, SampleFormat::Float32), 7, &[], false);
+    assert_eq!(&out[12..20], b"fmt \x28\x00\x00\x00");
+    // WAVE_FORMAT_EXTENSIBLE, 1 channel, 22050 Hz, 4 bytes/frame, 32 bits
+    assert_eq!(&out[20..22], &0xfffeu16.to_le_bytes());
+    assert_eq!(&out[22..24], &1u16.to_le_bytes());
+    assert_eq!(u32_at(&out, 28), 22050 * 4);
+    assert_eq!(&out[32..36], &[4, 0, 32, 0]);
+    // cbSize, valid bits, front center, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+    assert_eq!(&out[36..40], &[22, 0, 32, 0]);
+    assert_eq!(u32_at(&out, 40), 0x4);
+    assert_eq!(
+        &out[44..60],
+        &[0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71]
+    );
+    // float needs the frame count in a fact chunk
+    assert_eq!(&out[60..68], b"fact\x04\x00\x00\x00");
+    assert_eq!(u32_at(&out, 68), 7);
+    assert_eq!(&out[72..76], b"data");
+    assert_eq!(u32_at(&out, 76), 28);
+    assert_eq!(u32_at(&out, 4), out.len() as u32 - 8 + 28);
+    assert_eq!(read_header(&out).unwrap(), (format(1, SampleFormat::Float32), 7));
+}
+
+#[test]
+fn pcm24_header_is_extensible() {
+    let out = header(&format(2, SampleFormat::Int24), 3, &[], false);
+    assert_eq!(&out[20..22], &0xfffeu16.to_le_bytes());
+    assert_eq!(&out[32..36], &[6, 0, 24, 0]);
+    // front left and right, KSDATAFORMAT_SUBTYPE_PCM
+    assert_eq!(u32_at(&out, 40), 0x3);
+    assert_eq!(&out[44..46], &[0x01, 0x00]);
+    // no fact chunk for PCM
+    assert_eq!(&out[60..64], b"data");
+    assert_eq!(u32_at(&out, 64), 18);
+    assert_eq!(read_header(&out).unwrap(), (format(2, SampleFormat::Int24), 3));
+
+    // 9 bytes of mono data get a pad byte, counted in the RIFF size
+    let out = header(&format(1, SampleFormat::Int24), 3, &[], false);
+    assert_eq!(u32_at(&out, 40), 0x4);
+    assert_eq!(u32_at(&out, out.len() - 4), 9);
+    assert_eq!(u32_at(&out, 4), out.len() as u32 - 8 + 9 + 1);
+}
+
+#[test]
+fn rf64_header() {
+    // 6 GiB of stereo 16-bit data
+    let frames = 6u64 << 28;
+    let data_size = frames * 4;
+    let out = header(&format(2, SampleFormat::Int16), frames, &[], false);
+    assert_eq!(&out[0..4], b"RF64");
+    assert_eq!(u32_at(&out, 4), u32::MAX);
+    assert_eq!(&out[8..20], b"WAVEds64\x1c\x00\x00\x00");
+    let riff_size = out.len() as u64 - 8 + data_size;
+    assert_eq!(u64_at(&out, 20), riff_size);
+    assert_eq!(u64_at(&out, 28), data_size);
+    assert_eq!(u64_at(&out, 36), frames);
+    assert_eq!(u32_at(&out, 44), 0);
+    assert_eq!(&out[48..52], b"fmt ");
+    assert_eq!(&out[out.len() - 8..out.len() - 4], b"data");
+    assert_eq!(u32_at(&out, out.len() - 4), u32::MAX);
+    assert_eq!(read_header(&out).unwrap(), (format(2, SampleFormat::Int16), frames));
+}
+
+#[test]
+fn reserved_ds64() {
+    // room for ds64 in a JUNK chunk keeps the header length the same whatever the length
+    let short = header(&format(2, SampleFormat::Int16), 10, &[], true);
+    let long = header(&format(2, SampleFormat::Int16), 6 << 28, &[], true);
+    assert_eq!(short.len(), long.len());
+    assert_eq!(&short[0..4], b"RIFF");
+    assert_eq!(&short[12..20], b"JUNK\x1c\x00\x00\x00");
+    assert_eq!(&short[20..48], &[0; 28]);
+    assert_eq!(u32_at(&short, 4), short.len() as u32 - 8 + 40);
+    assert_eq!(&long[0..4], b"RF64");
+    assert_eq!(&long[12..16], b"ds64");
+    assert_eq!(read_header(&short).unwrap().1, 10);
+}
+
+#[test]
+fn writer_round_trip() {
+    let samples = (0..1001).map(|n| (n * 37 % 65536 - 32768) as i16).collect::<Vec<_>>();
+    // odd sized 24-bit data, padded
+    let format = format(1, SampleFormat::Int24);
+    let mut writer = WavWriter::streaming(Cursor::new(vec![]), format, vec![]).unwrap();
+    for piece in samples.chunks(100) {
+        writer.write_i16(piece).unwrap();
+    }
+    let out = writer.finalize().unwrap().into_inner();
+    assert_eq!(read_header(&out).unwrap(), (format, 1001));
+    // the patched header is the one for the final length, then the data and a pad byte
+    let expected = header(&format, 1001, &[], true);
+    assert_eq!(&out[..expected.len()], &expected);
+    let data = &out[expected.len()..];
+    assert_eq!(data.len(), 3004);
+    let decoded = data[..3003].chunks(3).map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 16) as i16).collect::<Vec<_>>();
+    assert_eq!(decoded, samples);
+    assert_eq!(data[3003], 0);
+
+    // with the length upfront the header is final right away
+    let mut writer = WavWriter::new(Cursor::new(vec![]), format, 1001, vec![]).unwrap();
+    writer.write_i16(&samples).unwrap();
+    let known = writer.finish().unwrap().into_inner();
+    assert_eq!(read_header(&known).unwrap(), (format, 1001));
+    assert_eq!(&known[known.len() - 3004..], data);
+}
+
+#[test]
+fn writer_checks_declared_length() {
+    let format = format(2, SampleFormat::Float32);
+    let mut writer = WavWriter::new(Cursor::new(vec![]), format, 2, vec![]).unwrap();
+    writer.write_f32(&[0.5, -0.5]).unwrap();
+    assert!(writer.finish().is_err());
+    let mut writer = WavWriter::new(Cursor::new(vec![]), format, 2, vec![]).unwrap();
+    assert!(writer.write_f32(&[0.0; 6]).is_err());
+
+    let mut writer = WavWriter::new(Cursor::new(vec![]), format, 2, vec![]).unwrap();
+    writer.write_f32(&[0.5, -0.5, 0.25, 1.0]).unwrap();
+    let out = writer.finish().unwrap().into_inner();
+    let data = &out[out.len() - 16..];
+    let decoded = data.chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>();
+    assert_eq!(decoded, [0.5, -0.5, 0.25, 1.0]);
+}